@@ -1,29 +1,80 @@
-use std::{error::Error, mem::transmute, time::Duration};
+use std::{error::Error, fs, mem::transmute, sync::Arc, time::Duration};
 
-use addin1c::{cstr1c, AddinResult, CStr1C, MethodInfo, Methods, PropInfo, SimpleAddin, Variant};
+use addin1c::{
+    cstr1c, AddinResult, CStr1C, MethodInfo, Methods, ParamValue, PropInfo, SimpleAddin, Variant,
+};
 use chrono::{DateTime, Utc};
 use futures::StreamExt;
 use rabbitmq_stream_client::{
     error::{ClientError, ConsumerStoreOffsetError},
     types::{Delivery, OffsetSpecification, ResponseCode, SimpleValue},
-    Consumer,
+    Consumer, Environment, FilterConfiguration,
+};
+use tokio::time;
+
+use crate::{
+    configuration, diagnostics, environment_builder, environment_impl, message_json,
+    prefetch::{PrefetchLimits, Prefetcher},
+    runtime::{self, SharedRuntime},
+    stream_stats::{Connector, StreamStats},
+    text_encoding::TextEncoding,
+    time_zone::TimeZone,
+    tls_data,
 };
-use tokio::{runtime::Runtime, time};
 
-use crate::{environment_builder, environment_impl};
+/// How long to wait for a chunk that already exists in the stream when no connect
+/// timeout is set.
+const DEFAULT_DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Default)]
 struct ConsumerProperties {
     pub name: Option<String>,
+    pub offset: Option<OffsetSpecification>,
+    pub single_active_consumer: bool,
+    pub filter_values: Vec<String>,
+    pub match_unfiltered: bool,
+    pub prefetch: PrefetchLimits,
+    pub read_until_end: bool,
+    pub replay: Option<Replay>,
+}
+
+/// Replay of a window: starts regardless of the stored offset and stops at `end`.
+#[derive(Clone)]
+struct Replay {
+    start: OffsetSpecification,
+    /// Only `Offset` or `Timestamp`, `None` to read on without a bound.
+    end: Option<OffsetSpecification>,
+}
+
+/// The end of the stream snapshotted at `Build` in the "read until end" mode.
+struct ReadRange {
+    last_offset: Option<u64>,
+    reached: bool,
+}
+
+enum Received {
+    Delivery(Delivery),
+    Timeout,
+    /// The read range is over, nothing more will be received.
+    End,
 }
 
 pub struct AddinConsumer {
     environment_builder: environment_builder::Builder,
     consumer_properties: Option<Box<ConsumerProperties>>,
-    runtime: Runtime,
-    consumer: Option<Consumer>,
+    consumer: Option<Prefetcher>,
+    stream: Option<String>,
+    /// Statistics of the built consumer's stream are queried through it.
+    connector: Option<Arc<Connector>>,
+    range: Option<ReadRange>,
     delivery: Option<Delivery>,
+    /// A receive error that `RecvJson` deferred to return the messages it had collected.
+    pending_error: Option<Box<dyn Error>>,
+    time_zone: TimeZone,
+    json_utf8_bodies: bool,
     last_error: Option<Box<dyn Error>>,
+    /// Dropped last: closing the connections needs it running.
+    runtime: SharedRuntime,
 }
 
 impl AddinConsumer {
@@ -31,13 +82,16 @@ impl AddinConsumer {
         Self {
             environment_builder: environment_builder::Builder::new(),
             consumer_properties: Some(Default::default()),
-            runtime: tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .expect("Failed to create runtime"),
             consumer: None,
+            stream: None,
+            connector: None,
+            range: None,
             delivery: None,
+            pending_error: None,
+            time_zone: TimeZone::Utc,
+            json_utf8_bodies: false,
             last_error: None,
+            runtime: SharedRuntime::new(),
         }
     }
 
@@ -59,59 +113,383 @@ impl AddinConsumer {
 
     fn build(&mut self, stream: &mut Variant, _ret_value: &mut Variant) -> AddinResult {
         let stream = stream.get_string()?;
-        let environment = self.runtime.block_on(self.environment_builder.build())?;
+        if self.consumer.as_ref().is_some_and(|x| !x.is_closed()) {
+            return Err("Consumer already built".into());
+        }
+        if let Some(props) = self.consumer_properties.as_deref() {
+            let bounded =
+                props.read_until_end || props.replay.as_ref().is_some_and(|x| x.end.is_some());
+            if bounded && !props.filter_values.is_empty() {
+                return Err(
+                    "Filters cannot be combined with a read range: chunks skipped by \
+                     the broker are never delivered, so the end of the range is not detected"
+                        .into(),
+                );
+            }
+        }
+        let (connection, policy) = self.environment()?;
+        let role = environment_builder::StreamRole::Consumer;
 
-        let consumer_properties = *self
+        let consumer_properties = self
             .consumer_properties
-            .take()
+            .as_deref()
             .ok_or("ConsumerBuilder not exists")?;
 
-        let mut builder = environment.consumer();
-        if let Some(name) = &consumer_properties.name {
-            builder = builder.name(name);
-        }
+        let replay = consumer_properties.replay.as_ref();
+        let stored_offset = match &consumer_properties.name {
+            // a replay starts at its own position
+            Some(name) if replay.is_none() => {
+                let temp_consumer =
+                    self.runtime.block_on(policy.run("Creating consumer", || {
+                        connection.create(&stream, role, |environment| {
+                            environment.consumer().name(name).build(&stream)
+                        })
+                    }))?;
+                let stored_offset = self.runtime.block_on(temp_consumer.query_offset());
+                let _ = self.runtime.block_on(temp_consumer.handle().close());
+                match stored_offset {
+                    Ok(offset) => Some(offset + 1),
+                    Err(ConsumerStoreOffsetError::Client(ClientError::RequestError(
+                        ResponseCode::OffsetNotFound,
+                    ))) => None,
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            _ => None,
+        };
+        let offset = match (replay, stored_offset) {
+            (Some(replay), _) => replay.start.clone(),
+            (None, Some(offset)) => OffsetSpecification::Offset(offset),
+            (None, None) => consumer_properties
+                .offset
+                .clone()
+                .unwrap_or(OffsetSpecification::Offset(0)),
+        };
 
-        let temp_consumer = self.runtime.block_on(builder.build(&stream))?;
-        let first_offset = match self.runtime.block_on(temp_consumer.query_offset()) {
-            Ok(offset) => offset + 1,
-            Err(ConsumerStoreOffsetError::Client(ClientError::RequestError(
-                ResponseCode::OffsetNotFound,
-            ))) => 0,
-            Err(err) => return Err(err.into()),
+        let builder = |environment: Environment| {
+            let mut builder = environment.consumer();
+            if let Some(name) = &consumer_properties.name {
+                builder = builder.name(name);
+            }
+            builder = builder
+                .offset(offset.clone())
+                .enable_single_active_consumer(consumer_properties.single_active_consumer);
+            if !consumer_properties.filter_values.is_empty() {
+                builder = builder.filter_input(Some(FilterConfiguration::new(
+                    consumer_properties.filter_values.clone(),
+                    consumer_properties.match_unfiltered,
+                )));
+            }
+            builder
         };
 
-        let mut builder = environment.consumer();
-        if let Some(name) = &consumer_properties.name {
-            builder = builder.name(name);
+        // snapshot before subscribing, so that messages published meanwhile stay out of range,
+        // `Some(None)` is an empty range
+        let mut last_offset = None;
+        // only a range needs the statistics connection and its credentials
+        let mut connector = None;
+        let mut stream_end = || -> Result<Option<u64>, Box<dyn Error>> {
+            let connector = match &connector {
+                Some(connector) => Arc::clone(connector),
+                None => connector
+                    .insert(self.environment_builder.connector()?)
+                    .clone(),
+            };
+            let stats = self.runtime.block_on(connector.stream_stats(&stream))?;
+            stats.end_offset()
+        };
+        match replay.and_then(|x| x.end.as_ref()) {
+            Some(OffsetSpecification::Offset(end)) => last_offset = Some(Some(*end)),
+            Some(OffsetSpecification::Timestamp(end)) => {
+                if *end > Utc::now().timestamp_millis() {
+                    return Err("Replay end is in the future, its offset is not known yet".into());
+                }
+                last_offset = Some(match stream_end()? {
+                    Some(stream_end) => {
+                        let consumer =
+                            self.runtime.block_on(policy.run("Creating consumer", || {
+                                connection.create(&stream, role, |environment| {
+                                    environment
+                                        .consumer()
+                                        .offset(OffsetSpecification::Timestamp(*end))
+                                        .build(&stream)
+                                })
+                            }))?;
+                        let timeout = policy.timeout.unwrap_or(DEFAULT_DELIVERY_TIMEOUT);
+                        let first_after = self
+                            .runtime
+                            .block_on(first_delivery_offset(consumer, timeout))?;
+                        // a chunk written after the snapshot: all of the snapshot is before the end
+                        first_after.checked_sub(1).min(Some(stream_end))
+                    }
+                    None => None,
+                });
+            }
+            _ => (),
         }
-        builder = builder.offset(OffsetSpecification::Offset(first_offset));
+        if consumer_properties.read_until_end {
+            let stream_end = stream_end()?;
+            last_offset = Some(match last_offset {
+                Some(last) => last.zip(stream_end).map(|(a, b)| a.min(b)),
+                None => stream_end,
+            });
+        }
+        let range = last_offset.map(|last_offset| {
+            let reached = match (&offset, last_offset) {
+                (_, None) | (OffsetSpecification::Next, _) => true,
+                (OffsetSpecification::Offset(offset), Some(last)) => *offset > last,
+                _ => false,
+            };
+            ReadRange {
+                last_offset,
+                reached,
+            }
+        });
 
-        let consumer = self.runtime.block_on(builder.build(&stream))?;
-        self.consumer = Some(consumer);
+        let consumer = self.runtime.block_on(policy.run("Creating consumer", || {
+            connection.create(&stream, role, |environment| {
+                builder(environment).build(&stream)
+            })
+        }))?;
+        let _guard = self.runtime.handle().enter();
+        self.consumer = Some(Prefetcher::start(consumer, consumer_properties.prefetch));
+        self.stream = Some(stream);
+        self.connector = connector;
+        self.range = range;
+        self.pending_error = None;
 
         Ok(())
     }
 
-    fn recv(&mut self, timeout: &mut Variant, ret_value: &mut Variant) -> AddinResult {
-        let timeout = timeout.get_i32()? as _;
-        let Some(consumer) = &mut self.consumer else {
-            return Err("No consumer".into());
-        };
+    fn configure(&mut self, json: &mut Variant, _ret_value: &mut Variant) -> AddinResult {
+        let json = configuration::parse(&json.get_string()?)?;
+        let root = configuration::Section::root(&json)?;
+        root.check_keys(&["environment", "tls", "consumer"])?;
+
+        // validate the consumer section before changing anything
+        let mut consumer = None;
+        if let Some(section) = root.section("consumer")? {
+            section.check_keys(&[
+                "name",
+                "offset",
+                "singleActiveConsumer",
+                "filter",
+                "prefetch",
+                "readUntilEnd",
+                "replay",
+            ])?;
+            let offset = match section.value("offset") {
+                Some(value) => Some(
+                    parse_offset_specification(value)
+                        .map_err(|e| section.error("offset", &e.to_string()))?,
+                ),
+                None => None,
+            };
+            let filter = match section.section("filter")? {
+                Some(filter) => {
+                    filter.check_keys(&["values", "matchUnfiltered"])?;
+                    Some((
+                        filter.strings("values")?.unwrap_or_default(),
+                        filter.bool("matchUnfiltered")?.unwrap_or_default(),
+                    ))
+                }
+                None => None,
+            };
+            let prefetch = match section.section("prefetch")? {
+                Some(prefetch) => {
+                    prefetch.check_keys(&["maxCount", "maxBytes"])?;
+                    let limit = |key: &str| match prefetch.int::<usize>(key)? {
+                        Some(0) => Err(prefetch.error(key, "must be positive")),
+                        x => Ok(x),
+                    };
+                    Some((limit("maxCount")?, limit("maxBytes")?))
+                }
+                None => None,
+            };
+            let replay = match section.section("replay")? {
+                Some(replay) => {
+                    replay.check_keys(&["start", "end"])?;
+                    let start = replay
+                        .value("start")
+                        .ok_or_else(|| replay.error("start", "is required"))?;
+                    let start = parse_offset_specification(start)
+                        .map_err(|e| replay.error("start", &e.to_string()))?;
+                    let end = match replay.value("end") {
+                        Some(serde_json::Value::Null) | None => None,
+                        Some(value) => Some(
+                            parse_replay_end(value)
+                                .map_err(|e| replay.error("end", &e.to_string()))?,
+                        ),
+                    };
+                    Some(Replay { start, end })
+                }
+                None => None,
+            };
+            consumer = Some((
+                section.string("name")?,
+                offset,
+                section.bool("singleActiveConsumer")?,
+                filter,
+                prefetch,
+                section.bool("readUntilEnd")?,
+                replay,
+            ));
+        }
+
+        configuration::configure_environment(&mut self.environment_builder, &root)?;
+
+        if let Some((
+            name,
+            offset,
+            single_active_consumer,
+            filter,
+            prefetch,
+            read_until_end,
+            replay,
+        )) = consumer
+        {
+            let props = self
+                .consumer_properties
+                .as_mut()
+                .ok_or("Parameter cannot be set")?;
+            props.name = name.or(props.name.take());
+            props.offset = offset.or(props.offset.take());
+            props.single_active_consumer =
+                single_active_consumer.unwrap_or(props.single_active_consumer);
+            if let Some((values, match_unfiltered)) = filter {
+                props.filter_values = values;
+                props.match_unfiltered = match_unfiltered;
+            }
+            if let Some((max_count, max_bytes)) = prefetch {
+                props.prefetch.max_count = max_count.unwrap_or(props.prefetch.max_count);
+                props.prefetch.max_bytes = max_bytes.unwrap_or(props.prefetch.max_bytes);
+            }
+            props.read_until_end = read_until_end.unwrap_or(props.read_until_end);
+            props.replay = replay.or(props.replay.take());
+        }
+        Ok(())
+    }
+
+    fn export_configuration(&mut self, ret_value: &mut Variant) -> AddinResult {
+        let mut root = serde_json::Map::new();
+        configuration::export_environment(&self.environment_builder, &mut root);
+        if let Some(props) = &self.consumer_properties {
+            let mut consumer = serde_json::Map::new();
+            consumer.insert("name".into(), props.name.clone().into());
+            consumer.insert(
+                "offset".into(),
+                props
+                    .offset
+                    .as_ref()
+                    .map(offset_specification_to_json)
+                    .into(),
+            );
+            consumer.insert(
+                "singleActiveConsumer".into(),
+                props.single_active_consumer.into(),
+            );
+            let mut filter = serde_json::Map::new();
+            filter.insert("values".into(), props.filter_values.clone().into());
+            filter.insert("matchUnfiltered".into(), props.match_unfiltered.into());
+            consumer.insert("filter".into(), filter.into());
+            let mut prefetch = serde_json::Map::new();
+            prefetch.insert("maxCount".into(), props.prefetch.max_count.into());
+            prefetch.insert("maxBytes".into(), props.prefetch.max_bytes.into());
+            consumer.insert("prefetch".into(), prefetch.into());
+            consumer.insert("readUntilEnd".into(), props.read_until_end.into());
+            if let Some(replay) = &props.replay {
+                let mut value = serde_json::Map::new();
+                value.insert("start".into(), offset_specification_to_json(&replay.start));
+                value.insert(
+                    "end".into(),
+                    replay.end.as_ref().map(offset_specification_to_json).into(),
+                );
+                consumer.insert("replay".into(), value.into());
+            }
+            root.insert("consumer".into(), consumer.into());
+        }
+        ret_value.set_str1c(serde_json::to_string_pretty(&root)?)?;
+        Ok(())
+    }
+
+    /// Takes the next delivery, in the "read until end" mode only up to the end of the range.
+    fn next(&mut self, deadline: time::Instant) -> Result<Received, Box<dyn Error>> {
+        let consumer = self.consumer.as_ref().ok_or("No consumer")?;
+        if let Some(err) = self.pending_error.take() {
+            return Err(err);
+        }
+        if self.range.as_ref().is_some_and(|x| x.reached) {
+            return Ok(Received::End);
+        }
         let _guard = self.runtime.handle().enter();
-        let delivery = self.runtime.block_on(time::timeout(
-            Duration::from_millis(timeout),
-            consumer.next(),
-        ));
-        let Ok(delivery) = delivery else {
-            // timeout
-            ret_value.set_bool(false);
-            return Ok(());
+        let Some(delivery) = self.runtime.block_on(consumer.next(deadline))? else {
+            return Ok(Received::Timeout);
         };
-        let Some(delivery) = delivery else {
-            return Err("Stream closed".into());
+        if let Some(range) = &mut self.range {
+            let last = range.last_offset.unwrap_or_default();
+            range.reached = delivery.offset() >= last;
+            if delivery.offset() > last {
+                return Ok(Received::End);
+            }
+        }
+        Ok(Received::Delivery(delivery))
+    }
+
+    fn recv(&mut self, timeout: &mut Variant, ret_value: &mut Variant) -> AddinResult {
+        let timeout = timeout.get_i32()? as _;
+        let deadline = time::Instant::now() + Duration::from_millis(timeout);
+        match self.next(deadline)? {
+            Received::Delivery(delivery) => {
+                self.delivery = Some(delivery);
+                ret_value.set_bool(true);
+            }
+            Received::Timeout => ret_value.set_bool(false),
+            Received::End => ret_value.set_empty(),
+        }
+        Ok(())
+    }
+
+    fn recv_json(
+        &mut self,
+        max_count: &mut Variant,
+        timeout: &mut Variant,
+        ret_value: &mut Variant,
+    ) -> AddinResult {
+        let max_count = max_count.get_i32()?.max(0) as usize;
+        let timeout = timeout.get_i32()? as _;
+        let deadline = time::Instant::now() + Duration::from_millis(timeout);
+        let mut deliveries = Vec::new();
+        while deliveries.len() < max_count {
+            match self.next(deadline) {
+                Ok(Received::Delivery(delivery)) => deliveries.push(delivery),
+                Ok(_) => break,
+                // the collected messages are already taken from the consumer
+                Err(err) if !deliveries.is_empty() => {
+                    self.pending_error = Some(err);
+                    break;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        let json = message_json::deliveries_to_json(&deliveries, self.json_utf8_bodies)?;
+        if let Some(delivery) = deliveries.pop() {
+            self.delivery = Some(delivery);
+        }
+        ret_value.set_str1c(json)?;
+        Ok(())
+    }
+
+    fn set_json_body_encoding(
+        &mut self,
+        encoding: &mut Variant,
+        _ret_value: &mut Variant,
+    ) -> AddinResult {
+        self.json_utf8_bodies = match encoding.get_string()?.as_str() {
+            "utf8" => true,
+            "base64" => false,
+            x => return Err(format!("Unknown body encoding: {x}").into()),
         };
-        self.delivery = Some(delivery?);
-        ret_value.set_bool(true);
         Ok(())
     }
 
@@ -123,6 +501,32 @@ impl AddinConsumer {
         Ok(())
     }
 
+    fn save_message_body(&mut self, path: &mut Variant, _ret_value: &mut Variant) -> AddinResult {
+        let path = path.get_string()?;
+        let delivery = self.delivery.as_ref().ok_or("No message")?;
+        let data = delivery.message().data().unwrap_or_default();
+        fs::write(&path, data).map_err(|e| format!("Failed to write {path}: {e}"))?;
+        Ok(())
+    }
+
+    fn message_text(
+        &mut self,
+        encoding: &mut Variant,
+        replace_invalid: &mut Variant,
+        ret_value: &mut Variant,
+    ) -> AddinResult {
+        let encoding = TextEncoding::from_param(encoding)?;
+        let replace_invalid = match replace_invalid.get() {
+            ParamValue::Empty => false,
+            _ => replace_invalid.get_bool()?,
+        };
+        let delivery = self.delivery.as_ref().ok_or("No message")?;
+        let data = delivery.message().data().unwrap_or_default();
+        let text = encoding.decode(data, replace_invalid)?;
+        ret_value.set_str1c(text)?;
+        Ok(())
+    }
+
     fn application_property(&mut self, name: &mut Variant, ret_value: &mut Variant) -> AddinResult {
         let name = name.get_string()?;
         let delivery = self.delivery.as_ref().ok_or("No message")?;
@@ -177,7 +581,7 @@ impl AddinConsumer {
             SimpleValue::Char(_) => todo!(),
             SimpleValue::Timestamp(x) => {
                 let x: DateTime<Utc> = unsafe { transmute(x.clone()) };
-                ret_value.set_date(x.into());
+                ret_value.set_date(self.time_zone.timestamp_to_date(x));
             }
             SimpleValue::Uuid(uuid) => ret_value.set_str1c(uuid.to_string())?,
             SimpleValue::Binary(x) => ret_value.set_blob(x)?,
@@ -187,6 +591,225 @@ impl AddinConsumer {
         Ok(())
     }
 
+    fn set_prefetch(
+        &mut self,
+        max_count: &mut Variant,
+        max_bytes: &mut Variant,
+        _ret_value: &mut Variant,
+    ) -> AddinResult {
+        let max_count = max_count.get_i32()?;
+        let max_bytes = max_bytes.get_i32()?;
+        if max_count < 1 || max_bytes < 1 {
+            return Err("Prefetch limits must be positive".into());
+        }
+        if let Some(builder) = self.consumer_properties.as_mut() {
+            builder.prefetch = PrefetchLimits {
+                max_count: max_count as usize,
+                max_bytes: max_bytes as usize,
+            };
+        };
+        Ok(())
+    }
+
+    fn buffered_count(&mut self, ret_value: &mut Variant) -> AddinResult {
+        let count = self.consumer.as_ref().map_or(0, Prefetcher::buffered_count);
+        ret_value.set_i32(count.try_into()?);
+        Ok(())
+    }
+
+    /// Queries the statistics over a new connection, `timeout` limits the whole query.
+    fn stream_stats(&mut self, timeout: Option<Duration>) -> Result<StreamStats, Box<dyn Error>> {
+        let Some(stream) = &self.stream else {
+            return Err("No consumer".into());
+        };
+        let connector = match &self.connector {
+            Some(connector) => connector,
+            None => self.connector.insert(self.environment_builder.connector()?),
+        };
+        let Some(timeout) = timeout else {
+            return self.runtime.block_on(connector.stream_stats(stream));
+        };
+        self.runtime.block_on(async {
+            time::timeout(timeout, connector.stream_stats(stream))
+                .await
+                .map_err(|_| {
+                    let timeout = timeout.as_millis();
+                    format!("Stream statistics timed out after {timeout} ms")
+                })?
+        })
+    }
+
+    fn stream_stats_json(&mut self, ret_value: &mut Variant) -> AddinResult {
+        let stats = self.stream_stats(None)?;
+        ret_value.set_str1c(stats.to_json().to_string())?;
+        Ok(())
+    }
+
+    fn lag(&mut self, timeout: &mut Variant, ret_value: &mut Variant) -> AddinResult {
+        let timeout = stats_timeout(timeout)?;
+        let Some(offset) = self.delivery.as_ref().map(Delivery::offset) else {
+            ret_value.set_empty();
+            return Ok(());
+        };
+        match self.stream_stats(timeout)?.lag(offset) {
+            Some(lag) => ret_value.set_str1c(lag.to_string())?,
+            None => ret_value.set_empty(),
+        }
+        Ok(())
+    }
+
+    fn is_caught_up(&mut self, timeout: &mut Variant, ret_value: &mut Variant) -> AddinResult {
+        let timeout = stats_timeout(timeout)?;
+        if let Some(range) = &self.range {
+            ret_value.set_bool(range.reached);
+            return Ok(());
+        }
+        let end_offset = self.stream_stats(timeout)?.end_offset()?;
+        let offset = self.delivery.as_ref().map(Delivery::offset);
+        let caught_up = match (end_offset, offset) {
+            (None, _) => true,
+            (Some(end), Some(offset)) => offset >= end,
+            (Some(_), None) => false,
+        };
+        ret_value.set_bool(caught_up);
+        Ok(())
+    }
+
+    fn set_read_until_end(
+        &mut self,
+        enabled: &mut Variant,
+        _ret_value: &mut Variant,
+    ) -> AddinResult {
+        let enabled = enabled.get_bool()?;
+        if let Some(builder) = self.consumer_properties.as_mut() {
+            builder.read_until_end = enabled;
+        };
+        Ok(())
+    }
+
+    fn set_replay(
+        &mut self,
+        start: &mut Variant,
+        end: &mut Variant,
+        _ret_value: &mut Variant,
+    ) -> AddinResult {
+        let start = start.get_string()?;
+        let end = match end.get() {
+            ParamValue::Empty => String::new(),
+            _ => end.get_string()?,
+        };
+        let replay = match start.as_str() {
+            "" => None,
+            _ => Some(Replay {
+                start: parse_offset_specification(&parse_position(&start)?)?,
+                end: match end.as_str() {
+                    "" => None,
+                    _ => Some(parse_replay_end(&parse_position(&end)?)?),
+                },
+            }),
+        };
+        if let Some(builder) = self.consumer_properties.as_mut() {
+            builder.replay = replay;
+        };
+        Ok(())
+    }
+
+    fn subscription_id(&mut self, ret_value: &mut Variant) -> AddinResult {
+        let delivery = self.delivery.as_ref().ok_or("No message")?;
+        ret_value.set_i32(delivery.subscription_id() as _);
+        Ok(())
+    }
+
+    fn stream_name(&mut self, ret_value: &mut Variant) -> AddinResult {
+        let delivery = self.delivery.as_ref().ok_or("No message")?;
+        ret_value.set_str1c(delivery.stream().as_str())?;
+        Ok(())
+    }
+
+    fn creation_time_value(&self) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        let delivery = self.delivery.as_ref().ok_or("No message")?;
+        let creation_time = delivery
+            .message()
+            .properties()
+            .and_then(|x| x.creation_time.clone());
+        Ok(creation_time.and_then(message_json::timestamp_to_datetime))
+    }
+
+    fn creation_time(&mut self, ret_value: &mut Variant) -> AddinResult {
+        match self.creation_time_value()? {
+            Some(x) => ret_value.set_date(self.time_zone.timestamp_to_date(x)),
+            None => ret_value.set_empty(),
+        }
+        Ok(())
+    }
+
+    fn creation_time_text(&mut self, ret_value: &mut Variant) -> AddinResult {
+        match self.creation_time_value()? {
+            Some(x) => ret_value.set_str1c(self.time_zone.format(x))?,
+            None => ret_value.set_empty(),
+        }
+        Ok(())
+    }
+
+    fn creation_time_unix_ms(&mut self, ret_value: &mut Variant) -> AddinResult {
+        match self.creation_time_value()? {
+            Some(x) => ret_value.set_f64(x.timestamp_millis() as _),
+            None => ret_value.set_empty(),
+        }
+        Ok(())
+    }
+
+    fn timestamp_property(
+        &self,
+        name: &mut Variant,
+    ) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        let name = name.get_string()?;
+        let delivery = self.delivery.as_ref().ok_or("No message")?;
+        let value = delivery
+            .message()
+            .application_properties()
+            .and_then(|x| x.get(&name));
+        match value {
+            None | Some(SimpleValue::Null) => Ok(None),
+            Some(x) => match message_json::timestamp_to_datetime(x.clone()) {
+                Some(x) => Ok(Some(x)),
+                None => Err(format!("Property {name} is not a timestamp").into()),
+            },
+        }
+    }
+
+    fn timestamp_property_text(
+        &mut self,
+        name: &mut Variant,
+        ret_value: &mut Variant,
+    ) -> AddinResult {
+        match self.timestamp_property(name)? {
+            Some(x) => ret_value.set_str1c(self.time_zone.format(x))?,
+            None => ret_value.set_empty(),
+        }
+        Ok(())
+    }
+
+    fn timestamp_property_unix_ms(
+        &mut self,
+        name: &mut Variant,
+        ret_value: &mut Variant,
+    ) -> AddinResult {
+        match self.timestamp_property(name)? {
+            Some(x) => ret_value.set_f64(x.timestamp_millis() as _),
+            None => ret_value.set_empty(),
+        }
+        Ok(())
+    }
+
+    fn creation_time_milliseconds(&mut self, ret_value: &mut Variant) -> AddinResult {
+        match self.creation_time_value()? {
+            Some(x) => ret_value.set_i32(x.timestamp_subsec_millis() as _),
+            None => ret_value.set_empty(),
+        }
+        Ok(())
+    }
+
     fn offset(&mut self, ret_value: &mut Variant) -> AddinResult {
         let delivery = self.delivery.as_ref().ok_or("No message")?;
         let bytes = delivery.offset().to_le_bytes();
@@ -197,7 +820,7 @@ impl AddinConsumer {
     fn store_offset(&mut self, offset: &mut Variant, _ret_value: &mut Variant) -> AddinResult {
         let offset = offset.get_blob()?;
         let offset = u64::from_le_bytes(offset.try_into()?);
-        let Some(consumer) = &mut self.consumer else {
+        let Some(consumer) = &self.consumer else {
             return Err("No consumer".into());
         };
         self.runtime.block_on(consumer.store_offset(offset))?;
@@ -207,6 +830,114 @@ impl AddinConsumer {
     environment_impl! {}
 }
 
+/// Parses `"first"`, `"last"`, `"next"`, `{"offset": 123}` or `{"timestamp": "2024-01-01T00:00:00Z"}`.
+fn parse_offset_specification(
+    value: &serde_json::Value,
+) -> Result<OffsetSpecification, Box<dyn Error>> {
+    use serde_json::Value;
+
+    match value {
+        Value::String(x) => match x.as_str() {
+            "first" => Ok(OffsetSpecification::First),
+            "last" => Ok(OffsetSpecification::Last),
+            "next" => Ok(OffsetSpecification::Next),
+            _ => Err(format!("unknown offset '{x}'").into()),
+        },
+        Value::Object(x) if x.len() == 1 => match x.iter().next() {
+            Some((key, value)) if key == "offset" => {
+                let offset = match value {
+                    Value::Number(x) => x.as_u64(),
+                    Value::String(x) => x.parse().ok(),
+                    _ => None,
+                }
+                .ok_or("offset must be a non-negative integer")?;
+                Ok(OffsetSpecification::Offset(offset))
+            }
+            Some((key, value)) if key == "timestamp" => {
+                let millis = match value {
+                    Value::Number(x) => x.as_i64().ok_or("invalid timestamp")?,
+                    Value::String(x) => DateTime::parse_from_rfc3339(x)
+                        .map_err(|e| format!("invalid timestamp '{x}': {e}"))?
+                        .timestamp_millis(),
+                    _ => return Err("timestamp must be a string or a number".into()),
+                };
+                Ok(OffsetSpecification::Timestamp(millis))
+            }
+            _ => Err("expected {\"offset\": ...} or {\"timestamp\": ...}".into()),
+        },
+        _ => Err("expected 'first', 'last', 'next' or an object".into()),
+    }
+}
+
+/// The end of a replay is an offset (inclusive) or a timestamp (exclusive).
+fn parse_replay_end(value: &serde_json::Value) -> Result<OffsetSpecification, Box<dyn Error>> {
+    match parse_offset_specification(value)? {
+        x @ (OffsetSpecification::Offset(_) | OffsetSpecification::Timestamp(_)) => Ok(x),
+        _ => Err("expected {\"offset\": ...} or {\"timestamp\": ...}".into()),
+    }
+}
+
+/// A position given to a method: `first`, `last`, `next` or the `JSON` object.
+fn parse_position(text: &str) -> Result<serde_json::Value, Box<dyn Error>> {
+    match text.trim_start().starts_with('{') {
+        true => Ok(configuration::parse(text)?),
+        false => Ok(text.into()),
+    }
+}
+
+/// Offset of the first delivery, `None` if nothing arrives shortly, then closes the consumer.
+/// The broker delivers nothing until a chunk at or after the position is written.
+async fn first_delivery_offset(
+    mut consumer: Consumer,
+    timeout: Duration,
+) -> Result<u64, Box<dyn Error>> {
+    let delivery = time::timeout(timeout, consumer.next()).await;
+    let _ = consumer.handle().close().await;
+    match delivery {
+        Ok(Some(delivery)) => Ok(delivery?.offset()),
+        Ok(None) => Err("Stream closed".into()),
+        Err(_) => Err(format!(
+            "No message at or after the replay end was delivered within {} ms, \
+             if none were written after it use an offset end or the read until end mode",
+            timeout.as_millis()
+        )
+        .into()),
+    }
+}
+
+/// Timeout of a statistics query in milliseconds, `0` leaves the per host connect timeout.
+fn stats_timeout(timeout: &mut Variant) -> Result<Option<Duration>, Box<dyn Error>> {
+    match timeout.get_i32()? {
+        0 => Ok(None),
+        x if x > 0 => Ok(Some(Duration::from_millis(x as u64))),
+        _ => Err("Timeout must not be negative".into()),
+    }
+}
+
+fn offset_specification_to_json(offset: &OffsetSpecification) -> serde_json::Value {
+    match offset {
+        OffsetSpecification::First => "first".into(),
+        OffsetSpecification::Last => "last".into(),
+        OffsetSpecification::Next => "next".into(),
+        OffsetSpecification::Offset(x) => serde_json::json!({ "offset": x.to_string() }),
+        OffsetSpecification::Timestamp(x) => match DateTime::<Utc>::from_timestamp_millis(*x) {
+            Some(x) => serde_json::json!({ "timestamp": x.to_rfc3339() }),
+            None => serde_json::json!({ "timestamp": x }),
+        },
+    }
+}
+
+impl Drop for AddinConsumer {
+    fn drop(&mut self) {
+        // the connection is served by the shared runtime and would outlive the component
+        if let Some(consumer) = self.consumer.take() {
+            if !consumer.is_closed() {
+                self.runtime.block_on(consumer.close());
+            }
+        }
+    }
+}
+
 impl SimpleAddin for AddinConsumer {
     fn name() -> &'static CStr1C {
         cstr1c!("RabbitMQ.Stream.Consumer")
@@ -222,14 +953,82 @@ impl SimpleAddin for AddinConsumer {
                 name: cstr1c!("Recv"),
                 method: Methods::Method1(Self::recv),
             },
+            MethodInfo {
+                name: cstr1c!("SetPrefetch"),
+                method: Methods::Method2(Self::set_prefetch),
+            },
+            MethodInfo {
+                name: cstr1c!("BufferedCount"),
+                method: Methods::Method0(Self::buffered_count),
+            },
+            MethodInfo {
+                name: cstr1c!("SetReplay"),
+                method: Methods::Method2(Self::set_replay),
+            },
+            MethodInfo {
+                name: cstr1c!("SetReadUntilEnd"),
+                method: Methods::Method1(Self::set_read_until_end),
+            },
+            MethodInfo {
+                name: cstr1c!("RecvJson"),
+                method: Methods::Method2(Self::recv_json),
+            },
+            MethodInfo {
+                name: cstr1c!("SetJsonBodyEncoding"),
+                method: Methods::Method1(Self::set_json_body_encoding),
+            },
             MethodInfo {
                 name: cstr1c!("MessageBody"),
                 method: Methods::Method0(Self::message_body),
             },
+            MethodInfo {
+                name: cstr1c!("SaveMessageBody"),
+                method: Methods::Method1(Self::save_message_body),
+            },
+            MethodInfo {
+                name: cstr1c!("MessageText"),
+                method: Methods::Method2(Self::message_text),
+            },
             MethodInfo {
                 name: cstr1c!("ApplicationProperty"),
                 method: Methods::Method1(Self::application_property),
             },
+            MethodInfo {
+                name: cstr1c!("TimestampPropertyText"),
+                method: Methods::Method1(Self::timestamp_property_text),
+            },
+            MethodInfo {
+                name: cstr1c!("TimestampPropertyUnixMs"),
+                method: Methods::Method1(Self::timestamp_property_unix_ms),
+            },
+            MethodInfo {
+                name: cstr1c!("SetTimeZone"),
+                method: Methods::Method1(Self::set_time_zone),
+            },
+            MethodInfo {
+                name: cstr1c!("SubscriptionId"),
+                method: Methods::Method0(Self::subscription_id),
+            },
+            MethodInfo {
+                name: cstr1c!("StreamName"),
+                method: Methods::Method0(Self::stream_name),
+            },
+            MethodInfo {
+                name: cstr1c!("CreationTime"),
+                method: Methods::Method0(Self::creation_time),
+            },
+            MethodInfo {
+                name: cstr1c!("CreationTimeMilliseconds"),
+                method: Methods::Method0(Self::creation_time_milliseconds),
+            },
+            MethodInfo {
+                name: cstr1c!("CreationTimeText"),
+                method: Methods::Method0(Self::creation_time_text),
+            },
+            MethodInfo {
+                name: cstr1c!("CreationTimeUnixMs"),
+                method: Methods::Method0(Self::creation_time_unix_ms),
+            },
             MethodInfo {
                 name: cstr1c!("Offset"),
                 method: Methods::Method0(Self::offset),
@@ -238,14 +1037,66 @@ impl SimpleAddin for AddinConsumer {
                 name: cstr1c!("StoreOffset"),
                 method: Methods::Method1(Self::store_offset),
             },
+            MethodInfo {
+                name: cstr1c!("StreamStats"),
+                method: Methods::Method0(Self::stream_stats_json),
+            },
+            MethodInfo {
+                name: cstr1c!("Lag"),
+                method: Methods::Method1(Self::lag),
+            },
+            MethodInfo {
+                name: cstr1c!("IsCaughtUp"),
+                method: Methods::Method1(Self::is_caught_up),
+            },
+            MethodInfo {
+                name: cstr1c!("SetWorkerThreads"),
+                method: Methods::Method1(Self::set_worker_threads),
+            },
+            MethodInfo {
+                name: cstr1c!("Diagnose"),
+                method: Methods::Method1(Self::diagnose),
+            },
+            MethodInfo {
+                name: cstr1c!("Configure"),
+                method: Methods::Method1(Self::configure),
+            },
+            MethodInfo {
+                name: cstr1c!("ExportConfiguration"),
+                method: Methods::Method0(Self::export_configuration),
+            },
+            MethodInfo {
+                name: cstr1c!("SetUri"),
+                method: Methods::Method1(Self::set_uri),
+            },
             MethodInfo {
                 name: cstr1c!("SetHost"),
                 method: Methods::Method1(Self::set_host),
             },
+            MethodInfo {
+                name: cstr1c!("AddHost"),
+                method: Methods::Method1(Self::add_host),
+            },
+            MethodInfo {
+                name: cstr1c!("AddAddressMapping"),
+                method: Methods::Method4(Self::add_address_mapping),
+            },
+            MethodInfo {
+                name: cstr1c!("SetHostSelection"),
+                method: Methods::Method1(Self::set_host_selection),
+            },
             MethodInfo {
                 name: cstr1c!("SetPort"),
                 method: Methods::Method1(Self::set_port),
             },
+            MethodInfo {
+                name: cstr1c!("SetConnectTimeout"),
+                method: Methods::Method1(Self::set_connect_timeout),
+            },
+            MethodInfo {
+                name: cstr1c!("SetRetryPolicy"),
+                method: Methods::Method2(Self::set_retry_policy),
+            },
             MethodInfo {
                 name: cstr1c!("SetUsername"),
                 method: Methods::Method1(Self::set_username),
@@ -254,6 +1105,10 @@ impl SimpleAddin for AddinConsumer {
                 name: cstr1c!("SetPassword"),
                 method: Methods::Method1(Self::set_password),
             },
+            MethodInfo {
+                name: cstr1c!("SetToken"),
+                method: Methods::Method1(Self::set_token),
+            },
             MethodInfo {
                 name: cstr1c!("SetVirtualHost"),
                 method: Methods::Method1(Self::set_virtual_host),
@@ -266,6 +1121,22 @@ impl SimpleAddin for AddinConsumer {
                 name: cstr1c!("SetLoadBalancerMode"),
                 method: Methods::Method1(Self::set_load_balancer_mode),
             },
+            MethodInfo {
+                name: cstr1c!("SetTlsEnabled"),
+                method: Methods::Method1(Self::set_tls_enabled),
+            },
+            MethodInfo {
+                name: cstr1c!("SetTlsUseSystemRoots"),
+                method: Methods::Method1(Self::set_tls_use_system_roots),
+            },
+            MethodInfo {
+                name: cstr1c!("SetTlsInsecure"),
+                method: Methods::Method1(Self::set_tls_insecure),
+            },
+            MethodInfo {
+                name: cstr1c!("SetTlsServerName"),
+                method: Methods::Method1(Self::set_tls_server_name),
+            },
             MethodInfo {
                 name: cstr1c!("AddClientCertificatesKeys"),
                 method: Methods::Method2(Self::add_client_certificates_keys),
@@ -274,6 +1145,18 @@ impl SimpleAddin for AddinConsumer {
                 name: cstr1c!("AddRootCertificates"),
                 method: Methods::Method1(Self::add_root_certificates),
             },
+            MethodInfo {
+                name: cstr1c!("AddClientCertificatesKeysData"),
+                method: Methods::Method2(Self::add_client_certificates_keys_data),
+            },
+            MethodInfo {
+                name: cstr1c!("AddRootCertificatesData"),
+                method: Methods::Method1(Self::add_root_certificates_data),
+            },
+            MethodInfo {
+                name: cstr1c!("AddPkcs12"),
+                method: Methods::Method2(Self::add_pkcs12),
+            },
             MethodInfo {
                 name: cstr1c!("SetName"),
                 method: Methods::Method1(Self::set_name),
@@ -286,10 +1169,17 @@ impl SimpleAddin for AddinConsumer {
     }
 
     fn properties() -> &'static [PropInfo<Self>] {
-        &[PropInfo {
-            name: cstr1c!("LastError"),
-            getter: Some(Self::last_error),
-            setter: None,
-        }]
+        &[
+            PropInfo {
+                name: cstr1c!("LastError"),
+                getter: Some(Self::last_error),
+                setter: None,
+            },
+            PropInfo {
+                name: cstr1c!("ConnectedEndpoint"),
+                getter: Some(Self::connected_endpoint),
+                setter: None,
+            },
+        ]
     }
 }
@@ -1,13 +1,23 @@
 use addin1c::{cstr1c, AddinResult, CStr1C, MethodInfo, Methods, PropInfo, SimpleAddin, Variant};
-use chrono::{DateTime, Utc};
 use rabbitmq_stream_client::{
+    error::ProducerPublishError,
     types::{Message, ResponseCode, SimpleValue},
-    Dedup, NoDedup, Producer,
+    ConfirmationStatus, Dedup, Environment, NoDedup, Producer,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs, mem,
+    sync::{Arc, Mutex},
 };
-use std::{collections::HashMap, error::Error, mem};
-use tokio::runtime::Runtime;
 
-use crate::{environment_builder, environment_impl};
+use crate::{
+    configuration, diagnostics, environment_builder, environment_impl, message_json,
+    runtime::{self, SharedRuntime},
+    text_encoding::TextEncoding,
+    time_zone::{self, TimeZone},
+    tls_data,
+};
 
 #[derive(Default)]
 struct ProducerBuilder {
@@ -15,6 +25,54 @@ struct ProducerBuilder {
     pub batch_size: Option<usize>,
 }
 
+/// How `SetApplicationProperty` types 1C numbers.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum NumberMode {
+    /// `Int` when the value fits, otherwise `Double`.
+    #[default]
+    Double,
+    /// `Long` for every integer, `Double` only for fractional values.
+    Long,
+}
+
+impl NumberMode {
+    fn parse(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "double" => Ok(NumberMode::Double),
+            "long" => Ok(NumberMode::Long),
+            _ => Err(format!("Unknown number mode: {name}").into()),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            NumberMode::Double => "double",
+            NumberMode::Long => "long",
+        }
+    }
+
+    /// Types a 1C number, `strict` rejects integers that may have lost digits.
+    fn value(self, x: f64, strict: bool) -> Result<SimpleValue, Box<dyn Error>> {
+        let integer = x.fract() == 0.0;
+        if strict && integer && x.abs() > MAX_EXACT_INTEGER {
+            return Err(format!(
+                "Number {x} cannot be sent without precision loss, pass it as a string"
+            )
+            .into());
+        }
+        match self {
+            NumberMode::Long if integer && x >= i64::MIN as f64 && x < i64::MAX as f64 => {
+                Ok(SimpleValue::Long(x as _))
+            }
+            _ => Ok(SimpleValue::Double(x.into())),
+        }
+    }
+}
+
+/// Integers beyond it are not exact in `f64`, so digits may have been lost on the way from 1C.
+/// `2^53` itself is excluded, as `2^53 + 1` is rounded to it.
+const MAX_EXACT_INTEGER: f64 = ((1u64 << f64::MANTISSA_DIGITS) - 1) as f64;
+
 enum ProducerWrapper {
     ProducerDedup(Producer<Dedup>),
     ProducerNoDedup(Producer<NoDedup>),
@@ -22,10 +80,18 @@ enum ProducerWrapper {
 }
 
 impl ProducerWrapper {
+    fn is_open(&self) -> bool {
+        match self {
+            ProducerWrapper::ProducerDedup(producer) => !producer.is_closed(),
+            ProducerWrapper::ProducerNoDedup(producer) => !producer.is_closed(),
+            ProducerWrapper::Unknown => false,
+        }
+    }
+
     fn batch_send(
         &mut self,
         messages: Vec<Message>,
-        runtime: &Runtime,
+        runtime: &SharedRuntime,
     ) -> Result<Vec<ResponseCode>, Box<dyn Error>> {
         let mut result = match self {
             ProducerWrapper::ProducerDedup(producer) => {
@@ -43,17 +109,82 @@ impl ProducerWrapper {
             .map(|x| x.status().clone())
             .collect::<Vec<_>>())
     }
+
+    fn batch_send_no_confirm(
+        &mut self,
+        messages: Vec<Message>,
+        runtime: &SharedRuntime,
+        failures: &SendFailures,
+    ) -> Result<(), Box<dyn Error>> {
+        let failures = failures.clone();
+        let callback = move |result| {
+            failures.record(result);
+            async {}
+        };
+        match self {
+            ProducerWrapper::ProducerDedup(producer) => {
+                runtime.block_on(producer.batch_send(messages, callback))?
+            }
+            ProducerWrapper::ProducerNoDedup(producer) => {
+                runtime.block_on(producer.batch_send(messages, callback))?
+            }
+            ProducerWrapper::Unknown => return Err("No producer".into()),
+        };
+        Ok(())
+    }
+}
+
+/// Failed confirmations of messages sent without waiting for them. They arrive after
+/// `BatchSendNoConfirm` returns, so the next send reports them.
+#[derive(Clone, Default)]
+struct SendFailures(Arc<Mutex<Option<(usize, String)>>>);
+
+impl SendFailures {
+    fn record(&self, result: Result<ConfirmationStatus, ProducerPublishError>) {
+        let error = match result {
+            Ok(status) if status.confirmed() && status.status() == &ResponseCode::Ok => return,
+            Ok(status) => format!(
+                "Message {} is not confirmed: {:?}",
+                status.publishing_id(),
+                status.status()
+            ),
+            Err(err) => err.to_string(),
+        };
+        match &mut *self.0.lock().unwrap() {
+            Some((count, _)) => *count += 1,
+            failures => *failures = Some((1, error)),
+        }
+    }
+
+    /// Takes the failures recorded so far.
+    fn check(&self) -> Result<(), Box<dyn Error>> {
+        match self.0.lock().unwrap().take() {
+            None => Ok(()),
+            Some((1, error)) => {
+                Err(format!("A message sent without confirmation failed: {error}").into())
+            }
+            Some((count, error)) => Err(format!(
+                "{count} messages sent without confirmation failed, the first: {error}"
+            )
+            .into()),
+        }
+    }
 }
 
 pub struct AddinProducer {
     environment_builder: environment_builder::Builder,
     producer_builder: Option<Box<ProducerBuilder>>,
-    runtime: Runtime,
     producer: ProducerWrapper,
     messages: Vec<Message>,
     application_properties: HashMap<String, SimpleValue>,
+    time_zone: TimeZone,
+    number_mode: NumberMode,
+    strict_numbers: bool,
     statuses: Vec<ResponseCode>,
+    send_failures: SendFailures,
     last_error: Option<Box<dyn Error>>,
+    /// Dropped last: closing the connections needs it running.
+    runtime: SharedRuntime,
 }
 
 impl AddinProducer {
@@ -61,15 +192,16 @@ impl AddinProducer {
         Self {
             environment_builder: environment_builder::Builder::new(),
             producer_builder: Some(Box::new(ProducerBuilder::default())),
-            runtime: tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .expect("Failed to create runtime"),
             producer: ProducerWrapper::Unknown,
             messages: Vec::new(),
             application_properties: HashMap::new(),
+            time_zone: TimeZone::Utc,
+            number_mode: NumberMode::Double,
+            strict_numbers: false,
             statuses: Vec::new(),
+            send_failures: SendFailures::default(),
             last_error: None,
+            runtime: SharedRuntime::new(),
         }
     }
 
@@ -104,33 +236,143 @@ impl AddinProducer {
 
     fn build(&mut self, stream: &mut Variant, _ret_value: &mut Variant) -> AddinResult {
         let stream = stream.get_string()?;
-        let environment = self.runtime.block_on(self.environment_builder.build())?;
+        if self.producer.is_open() {
+            return Err("Producer already built".into());
+        }
+        let (connection, policy) = self.environment()?;
 
-        let producer_properties = *self
+        let producer_properties = self
             .producer_builder
-            .take()
+            .as_deref()
             .ok_or("ProducerBuilder not exists")?;
 
-        let mut producer_builder = environment.producer();
-        if let Some(size) = producer_properties.batch_size {
-            producer_builder = producer_builder.batch_size(size);
-        }
+        let producer_builder = |environment: Environment| {
+            let mut producer_builder = environment.producer();
+            if let Some(size) = producer_properties.batch_size {
+                producer_builder = producer_builder.batch_size(size);
+            }
+            producer_builder
+        };
+        let role = environment_builder::StreamRole::Producer;
 
-        self.producer = if let Some(name) = producer_properties.name {
-            let producer = self
-                .runtime
-                .block_on(producer_builder.name(&name).build(&stream))?;
+        self.producer = if let Some(name) = &producer_properties.name {
+            let producer = self.runtime.block_on(policy.run("Creating producer", || {
+                connection.create(&stream, role, |environment| {
+                    producer_builder(environment).name(name).build(&stream)
+                })
+            }))?;
             ProducerWrapper::ProducerDedup(producer)
         } else {
-            let producer = self.runtime.block_on(producer_builder.build(&stream))?;
+            let producer = self.runtime.block_on(policy.run("Creating producer", || {
+                connection.create(&stream, role, |environment| {
+                    producer_builder(environment).build(&stream)
+                })
+            }))?;
             ProducerWrapper::ProducerNoDedup(producer)
         };
 
         Ok(())
     }
 
+    fn configure(&mut self, json: &mut Variant, _ret_value: &mut Variant) -> AddinResult {
+        let json = configuration::parse(&json.get_string()?)?;
+        let root = configuration::Section::root(&json)?;
+        root.check_keys(&["environment", "tls", "producer"])?;
+
+        // validate the producer section before changing anything
+        let mut producer = None;
+        if let Some(section) = root.section("producer")? {
+            section.check_keys(&[
+                "name",
+                "batchSize",
+                "compression",
+                "numberMode",
+                "strictNumbers",
+            ])?;
+            if let Some(compression) = section.string("compression")? {
+                if compression != "none" {
+                    return Err(section.error(
+                        "compression",
+                        "only 'none' is supported by the client library",
+                    ));
+                }
+            }
+            let number_mode = match section.string("numberMode")? {
+                Some(mode) => Some(
+                    NumberMode::parse(&mode)
+                        .map_err(|e| section.error("numberMode", &e.to_string()))?,
+                ),
+                None => None,
+            };
+            producer = Some((
+                section.string("name")?,
+                section.int("batchSize")?,
+                number_mode,
+                section.bool("strictNumbers")?,
+            ));
+        }
+
+        configuration::configure_environment(&mut self.environment_builder, &root)?;
+
+        if let Some((name, batch_size, number_mode, strict_numbers)) = producer {
+            let builder = self
+                .producer_builder
+                .as_mut()
+                .ok_or("Parameter cannot be set")?;
+            builder.name = name.or(builder.name.take());
+            builder.batch_size = batch_size.or(builder.batch_size);
+            self.number_mode = number_mode.unwrap_or(self.number_mode);
+            self.strict_numbers = strict_numbers.unwrap_or(self.strict_numbers);
+        }
+        Ok(())
+    }
+
+    fn export_configuration(&mut self, ret_value: &mut Variant) -> AddinResult {
+        let mut root = serde_json::Map::new();
+        configuration::export_environment(&self.environment_builder, &mut root);
+        if let Some(builder) = &self.producer_builder {
+            let mut producer = serde_json::Map::new();
+            producer.insert("name".into(), builder.name.clone().into());
+            producer.insert("batchSize".into(), builder.batch_size.into());
+            producer.insert("compression".into(), "none".into());
+            producer.insert("numberMode".into(), self.number_mode.name().into());
+            producer.insert("strictNumbers".into(), self.strict_numbers.into());
+            root.insert("producer".into(), producer.into());
+        }
+        ret_value.set_str1c(serde_json::to_string_pretty(&root)?)?;
+        Ok(())
+    }
+
     fn add_message(&mut self, data: &mut Variant, _ret_value: &mut Variant) -> AddinResult {
-        let data = data.get_blob()?;
+        let data = data.get_blob()?.to_vec();
+        self.push_message(data);
+        Ok(())
+    }
+
+    fn add_message_text(
+        &mut self,
+        text: &mut Variant,
+        encoding: &mut Variant,
+        _ret_value: &mut Variant,
+    ) -> AddinResult {
+        let encoding = TextEncoding::from_param(encoding)?;
+        let data = encoding.encode(text.get_str1c()?)?;
+        self.push_message(data);
+        Ok(())
+    }
+
+    fn add_message_from_file(
+        &mut self,
+        path: &mut Variant,
+        _ret_value: &mut Variant,
+    ) -> AddinResult {
+        let path = path.get_string()?;
+        let data = fs::read(&path).map_err(|e| format!("Failed to read {path}: {e}"))?;
+        self.push_message(data);
+        Ok(())
+    }
+
+    fn push_message(&mut self, data: Vec<u8>) {
         let mut builder = Message::builder();
 
         if !data.is_empty() {
@@ -145,6 +387,12 @@ impl AddinProducer {
         }
 
         self.messages.push(builder.build());
+    }
+
+    fn add_messages_json(&mut self, text: &mut Variant, _ret_value: &mut Variant) -> AddinResult {
+        let text = text.get_string()?;
+        let messages = message_json::parse_messages(&text)?;
+        self.messages.extend(messages);
         Ok(())
     }
 
@@ -159,11 +407,13 @@ impl AddinProducer {
         let value = match value.get() {
             addin1c::ParamValue::Empty => SimpleValue::Null,
             addin1c::ParamValue::Bool(x) => SimpleValue::Boolean(x),
-            addin1c::ParamValue::I32(x) => SimpleValue::Int(x),
-            addin1c::ParamValue::F64(x) => SimpleValue::Double(x.into()),
+            addin1c::ParamValue::I32(x) => match self.number_mode {
+                NumberMode::Double => SimpleValue::Int(x),
+                NumberMode::Long => SimpleValue::Long(x as _),
+            },
+            addin1c::ParamValue::F64(x) => self.number_mode.value(x, self.strict_numbers)?,
             addin1c::ParamValue::Date(tm) => {
-                let datetime: DateTime<Utc> = tm.into();
-                SimpleValue::Timestamp(datetime.into())
+                SimpleValue::Timestamp(self.time_zone.date_to_timestamp(tm)?.into())
             }
             addin1c::ParamValue::Str(x) => SimpleValue::String(String::from_utf16_lossy(x)),
             addin1c::ParamValue::Blob(x) => SimpleValue::Binary(x.into()),
@@ -172,7 +422,44 @@ impl AddinProducer {
         Ok(())
     }
 
+    fn set_number_mode(
+        &mut self,
+        mode: &mut Variant,
+        strict: &mut Variant,
+        _ret_value: &mut Variant,
+    ) -> AddinResult {
+        self.number_mode = NumberMode::parse(&mode.get_string()?)?;
+        self.strict_numbers = match strict.get() {
+            addin1c::ParamValue::Empty => false,
+            _ => strict.get_bool()?,
+        };
+        Ok(())
+    }
+
+    fn set_application_property_timestamp(
+        &mut self,
+        key: &mut Variant,
+        value: &mut Variant,
+        _ret_value: &mut Variant,
+    ) -> AddinResult {
+        let key = key.get_string()?;
+        let timestamp = match value.get() {
+            addin1c::ParamValue::Str(x) => self
+                .time_zone
+                .parse_timestamp(&String::from_utf16_lossy(x))?,
+            addin1c::ParamValue::I32(x) => time_zone::from_unix_millis(x as _)?,
+            addin1c::ParamValue::F64(x) if x.fract() == 0.0 => time_zone::from_unix_millis(x as _)?,
+            addin1c::ParamValue::Date(tm) => self.time_zone.date_to_timestamp(tm)?,
+            _ => return Err("Timestamp must be a string, Unix milliseconds or a date".into()),
+        };
+        self.application_properties
+            .insert(key, SimpleValue::Timestamp(timestamp.into()));
+        Ok(())
+    }
+
     fn batch_send(&mut self, _ret_value: &mut Variant) -> AddinResult {
+        // the messages stay added when earlier ones failed
+        self.send_failures.check()?;
         let messages = std::mem::take(&mut self.messages);
         self.statuses = self.producer.batch_send(messages, &self.runtime)?;
         if self.statuses.iter().all(|x| x == &ResponseCode::Ok) {
@@ -182,6 +469,14 @@ impl AddinProducer {
         }
     }
 
+    fn batch_send_no_confirm(&mut self, _ret_value: &mut Variant) -> AddinResult {
+        self.send_failures.check()?;
+        let messages = std::mem::take(&mut self.messages);
+        self.statuses.clear();
+        self.producer
+            .batch_send_no_confirm(messages, &self.runtime, &self.send_failures)
+    }
+
     fn statuses(&mut self, ret_value: &mut Variant) -> AddinResult {
         use std::fmt::Write;
         let mut buf = String::new();
@@ -210,26 +505,94 @@ impl SimpleAddin for AddinProducer {
                 name: cstr1c!("AddMessage"),
                 method: Methods::Method1(Self::add_message),
             },
+            MethodInfo {
+                name: cstr1c!("AddMessageFromFile"),
+                method: Methods::Method1(Self::add_message_from_file),
+            },
+            MethodInfo {
+                name: cstr1c!("AddMessageText"),
+                method: Methods::Method2(Self::add_message_text),
+            },
+            MethodInfo {
+                name: cstr1c!("AddMessagesJson"),
+                method: Methods::Method1(Self::add_messages_json),
+            },
             MethodInfo {
                 name: cstr1c!("SetApplicationProperty"),
                 method: Methods::Method2(Self::set_application_property),
             },
+            MethodInfo {
+                name: cstr1c!("SetNumberMode"),
+                method: Methods::Method2(Self::set_number_mode),
+            },
+            MethodInfo {
+                name: cstr1c!("SetApplicationPropertyTimestamp"),
+                method: Methods::Method2(Self::set_application_property_timestamp),
+            },
+            MethodInfo {
+                name: cstr1c!("SetTimeZone"),
+                method: Methods::Method1(Self::set_time_zone),
+            },
             MethodInfo {
                 name: cstr1c!("BatchSend"),
                 method: Methods::Method0(Self::batch_send),
             },
+            MethodInfo {
+                name: cstr1c!("BatchSendNoConfirm"),
+                method: Methods::Method0(Self::batch_send_no_confirm),
+            },
             MethodInfo {
                 name: cstr1c!("Statuses"),
                 method: Methods::Method0(Self::statuses),
             },
+            MethodInfo {
+                name: cstr1c!("SetWorkerThreads"),
+                method: Methods::Method1(Self::set_worker_threads),
+            },
+            MethodInfo {
+                name: cstr1c!("Diagnose"),
+                method: Methods::Method1(Self::diagnose),
+            },
+            MethodInfo {
+                name: cstr1c!("Configure"),
+                method: Methods::Method1(Self::configure),
+            },
+            MethodInfo {
+                name: cstr1c!("ExportConfiguration"),
+                method: Methods::Method0(Self::export_configuration),
+            },
+            MethodInfo {
+                name: cstr1c!("SetUri"),
+                method: Methods::Method1(Self::set_uri),
+            },
             MethodInfo {
                 name: cstr1c!("SetHost"),
                 method: Methods::Method1(Self::set_host),
             },
+            MethodInfo {
+                name: cstr1c!("AddHost"),
+                method: Methods::Method1(Self::add_host),
+            },
+            MethodInfo {
+                name: cstr1c!("AddAddressMapping"),
+                method: Methods::Method4(Self::add_address_mapping),
+            },
+            MethodInfo {
+                name: cstr1c!("SetHostSelection"),
+                method: Methods::Method1(Self::set_host_selection),
+            },
             MethodInfo {
                 name: cstr1c!("SetPort"),
                 method: Methods::Method1(Self::set_port),
             },
+            MethodInfo {
+                name: cstr1c!("SetConnectTimeout"),
+                method: Methods::Method1(Self::set_connect_timeout),
+            },
+            MethodInfo {
+                name: cstr1c!("SetRetryPolicy"),
+                method: Methods::Method2(Self::set_retry_policy),
+            },
             MethodInfo {
                 name: cstr1c!("SetUsername"),
                 method: Methods::Method1(Self::set_username),
@@ -238,6 +601,10 @@ impl SimpleAddin for AddinProducer {
                 name: cstr1c!("SetPassword"),
                 method: Methods::Method1(Self::set_password),
             },
+            MethodInfo {
+                name: cstr1c!("SetToken"),
+                method: Methods::Method1(Self::set_token),
+            },
             MethodInfo {
                 name: cstr1c!("SetVirtualHost"),
                 method: Methods::Method1(Self::set_virtual_host),
@@ -250,6 +617,22 @@ impl SimpleAddin for AddinProducer {
                 name: cstr1c!("SetLoadBalancerMode"),
                 method: Methods::Method1(Self::set_load_balancer_mode),
             },
+            MethodInfo {
+                name: cstr1c!("SetTlsEnabled"),
+                method: Methods::Method1(Self::set_tls_enabled),
+            },
+            MethodInfo {
+                name: cstr1c!("SetTlsUseSystemRoots"),
+                method: Methods::Method1(Self::set_tls_use_system_roots),
+            },
+            MethodInfo {
+                name: cstr1c!("SetTlsInsecure"),
+                method: Methods::Method1(Self::set_tls_insecure),
+            },
+            MethodInfo {
+                name: cstr1c!("SetTlsServerName"),
+                method: Methods::Method1(Self::set_tls_server_name),
+            },
             MethodInfo {
                 name: cstr1c!("AddClientCertificatesKeys"),
                 method: Methods::Method2(Self::add_client_certificates_keys),
@@ -258,6 +641,18 @@ impl SimpleAddin for AddinProducer {
                 name: cstr1c!("AddRootCertificates"),
                 method: Methods::Method1(Self::add_root_certificates),
             },
+            MethodInfo {
+                name: cstr1c!("AddClientCertificatesKeysData"),
+                method: Methods::Method2(Self::add_client_certificates_keys_data),
+            },
+            MethodInfo {
+                name: cstr1c!("AddRootCertificatesData"),
+                method: Methods::Method1(Self::add_root_certificates_data),
+            },
+            MethodInfo {
+                name: cstr1c!("AddPkcs12"),
+                method: Methods::Method2(Self::add_pkcs12),
+            },
             MethodInfo {
                 name: cstr1c!("SetName"),
                 method: Methods::Method1(Self::set_name),
@@ -274,10 +669,93 @@ impl SimpleAddin for AddinProducer {
     }
 
     fn properties() -> &'static [PropInfo<Self>] {
-        &[PropInfo {
-            name: cstr1c!("LastError"),
-            getter: Some(Self::last_error),
-            setter: None,
-        }]
+        &[
+            PropInfo {
+                name: cstr1c!("LastError"),
+                getter: Some(Self::last_error),
+                setter: None,
+            },
+            PropInfo {
+                name: cstr1c!("ConnectedEndpoint"),
+                getter: Some(Self::connected_endpoint),
+                setter: None,
+            },
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_exact_integer_is_the_last_unambiguous_one() {
+        assert_eq!(MAX_EXACT_INTEGER, 9007199254740991.0);
+        // 2^53 + 1 is not representable and arrives from 1C as 2^53
+        assert_eq!(9007199254740993u64 as f64, MAX_EXACT_INTEGER + 1.0);
+    }
+
+    #[test]
+    fn send_failures_are_reported_once() {
+        let failures = SendFailures::default();
+        assert!(failures.check().is_ok());
+        failures.record(Err(ProducerPublishError::Closed));
+        assert_eq!(
+            failures.check().unwrap_err().to_string(),
+            "A message sent without confirmation failed: \
+             Failed to publish message, the producer is closed"
+        );
+        assert!(failures.check().is_ok());
+
+        failures.record(Err(ProducerPublishError::Closed));
+        failures.record(Err(ProducerPublishError::Timeout));
+        assert_eq!(
+            failures.check().unwrap_err().to_string(),
+            "2 messages sent without confirmation failed, the first: \
+             Failed to publish message, the producer is closed"
+        );
+    }
+
+    #[test]
+    fn numbers_follow_the_mode() {
+        let value = |mode: NumberMode, x: f64| mode.value(x, false).unwrap();
+        assert_eq!(
+            value(NumberMode::Double, 5.0),
+            SimpleValue::Double(5.0.into())
+        );
+        assert_eq!(value(NumberMode::Long, 5.0), SimpleValue::Long(5));
+        assert_eq!(value(NumberMode::Long, -5.0), SimpleValue::Long(-5));
+        assert_eq!(
+            value(NumberMode::Long, 1.5),
+            SimpleValue::Double(1.5.into())
+        );
+        assert_eq!(
+            value(NumberMode::Long, i64::MIN as f64),
+            SimpleValue::Long(i64::MIN)
+        );
+        // 2^63 does not fit into i64
+        assert_eq!(
+            value(NumberMode::Long, i64::MAX as f64),
+            SimpleValue::Double((i64::MAX as f64).into())
+        );
+    }
+
+    #[test]
+    fn strict_mode_rejects_inexact_integers() {
+        for mode in [NumberMode::Double, NumberMode::Long] {
+            assert!(mode.value(MAX_EXACT_INTEGER, true).is_ok());
+            assert!(mode.value(-MAX_EXACT_INTEGER, true).is_ok());
+            assert_eq!(
+                mode.value(MAX_EXACT_INTEGER + 1.0, true)
+                    .err()
+                    .unwrap()
+                    .to_string(),
+                "Number 9007199254740992 cannot be sent without precision loss, pass it as a string"
+            );
+            assert!(mode.value(-MAX_EXACT_INTEGER - 1.0, true).is_err());
+            // fractional values are approximate anyway
+            assert!(mode.value(0.1, true).is_ok());
+            assert!(mode.value(MAX_EXACT_INTEGER * 2.0, false).is_ok());
+        }
     }
 }
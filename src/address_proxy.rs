@@ -0,0 +1,259 @@
+use std::{
+    error::Error,
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use rustls::{pki_types::ServerName, ClientConfig};
+use tokio::{
+    io::{copy_bidirectional, AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+    time,
+};
+use tokio_rustls::TlsConnector;
+
+/// Where the proxy forwards connections: the real address of a broker and, with TLS,
+/// the name its certificate is checked against.
+#[derive(Clone)]
+pub struct Target {
+    pub address: (String, u16),
+    pub server_name: String,
+}
+
+impl Target {
+    fn name(&self) -> String {
+        format!("{}:{}", self.address.0, self.address.1)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ProxySettings {
+    /// Performs the TLS handshake with the broker, the client library connects without TLS.
+    pub tls: Option<Arc<ClientConfig>>,
+    /// Checked against the certificate of every target instead of its own name.
+    pub server_name: Option<String>,
+    /// Deadline to connect to a target, including the TLS handshake.
+    pub timeout: Option<Duration>,
+    /// Number of connections accepted before the proxy stops listening.
+    pub limit: Option<usize>,
+}
+
+trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+/// Local TCP proxy in front of the brokers.
+///
+/// The client library reads certificates only from files and checks them against the host it
+/// connects to, so with TLS it connects to the proxy without TLS and the proxy performs the
+/// handshake itself.
+///
+/// The client library also connects a producer or consumer to the host a node advertises,
+/// which may be unreachable from here. Through the proxy the library connects to `127.0.0.1`
+/// in load balancer mode, that is, reconnects until it reaches the node it needs, while the
+/// proxy forwards the connections only to the real addresses of the nodes of that producer or
+/// consumer. The connections already forwarded stay open when the proxy is dropped.
+pub struct AddressProxy {
+    address: SocketAddr,
+    shared: Arc<Shared>,
+    task: JoinHandle<()>,
+}
+
+struct Shared {
+    targets: Vec<Target>,
+    settings: ProxySettings,
+    /// Why the last connection could not be forwarded, cleared by a forwarded one.
+    error: Mutex<Option<String>>,
+    limit_reached: AtomicBool,
+}
+
+impl AddressProxy {
+    /// Must be called within a tokio runtime.
+    pub async fn start(targets: Vec<Target>, settings: ProxySettings) -> io::Result<Self> {
+        if targets.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "No targets"));
+        }
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let address = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            targets,
+            settings,
+            error: Default::default(),
+            limit_reached: AtomicBool::new(false),
+        });
+        let task = tokio::spawn({
+            let shared = shared.clone();
+            async move {
+                let mut accepted = 0;
+                while let Ok((inbound, _)) = listener.accept().await {
+                    let target = accepted % shared.targets.len();
+                    tokio::spawn(shared.clone().forward(inbound, target));
+                    accepted += 1;
+                    if shared.settings.limit.is_some_and(|x| accepted >= x) {
+                        shared.limit_reached.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            address,
+            shared,
+            task,
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Replaces an error of the client library with its cause on the proxy side, if any:
+    /// the library only sees that the connection to the proxy was closed or refused.
+    pub fn failure(&self, err: Box<dyn Error>) -> Box<dyn Error> {
+        if let Some(error) = self.shared.error.lock().unwrap().take() {
+            return error.into();
+        }
+        match self.shared.settings.limit {
+            Some(limit) if self.shared.limit_reached.load(Ordering::Relaxed) => {
+                let targets = self
+                    .shared
+                    .targets
+                    .iter()
+                    .map(Target::name)
+                    .collect::<Vec<_>>();
+                format!(
+                    "The client did not reach the node it needs within {limit} connections \
+                     to {}: {err}",
+                    targets.join(", ")
+                )
+                .into()
+            }
+            _ => err,
+        }
+    }
+}
+
+impl Shared {
+    async fn forward(self: Arc<Self>, mut inbound: TcpStream, target: usize) {
+        let target = &self.targets[target];
+        let outbound = match self.settings.timeout {
+            Some(timeout) => time::timeout(timeout, self.connect(target))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(format!("Timed out after {} ms", timeout.as_millis()).into())
+                }),
+            None => self.connect(target).await,
+        }
+        .map_err(|e| e.to_string());
+        match outbound {
+            Ok(mut outbound) => {
+                *self.error.lock().unwrap() = None;
+                let _ = copy_bidirectional(&mut inbound, &mut outbound).await;
+            }
+            Err(err) => {
+                let error = format!("Connecting to {}: {err}", target.name());
+                *self.error.lock().unwrap() = Some(error);
+            }
+        }
+    }
+
+    async fn connect(&self, target: &Target) -> Result<Box<dyn Transport>, Box<dyn Error>> {
+        let tcp = TcpStream::connect(target.address.clone()).await?;
+        match &self.settings.tls {
+            Some(tls) => {
+                let server_name = self.settings.server_name.as_ref();
+                let server_name = server_name.unwrap_or(&target.server_name);
+                let server_name = ServerName::try_from(server_name.clone())?;
+                let tls = TlsConnector::from(tls.clone())
+                    .connect(server_name, tcp)
+                    .await?;
+                Ok(Box::new(tls))
+            }
+            None => Ok(Box::new(tcp)),
+        }
+    }
+}
+
+impl Drop for AddressProxy {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn target() -> (TcpListener, Target) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let target = Target {
+            address: ("127.0.0.1".to_owned(), port),
+            server_name: "localhost".to_owned(),
+        };
+        (listener, target)
+    }
+
+    /// A port nothing listens on.
+    async fn closed_target() -> Target {
+        let (listener, target) = target().await;
+        drop(listener);
+        target
+    }
+
+    #[tokio::test]
+    async fn routes_connections_in_turn() {
+        let (first, first_target) = target().await;
+        let (second, second_target) = target().await;
+        let proxy = AddressProxy::start(vec![first_target, second_target], Default::default())
+            .await
+            .unwrap();
+
+        for listener in [&first, &second, &first] {
+            let _inbound = TcpStream::connect(proxy.address()).await.unwrap();
+            listener.accept().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_unreachable_target() {
+        let unreachable = closed_target().await;
+        let name = unreachable.name();
+        let proxy = AddressProxy::start(vec![unreachable], Default::default())
+            .await
+            .unwrap();
+
+        let mut inbound = TcpStream::connect(proxy.address()).await.unwrap();
+        // the proxy closes the connection it could not forward
+        let mut buffer = [0; 1];
+        let read = tokio::io::AsyncReadExt::read(&mut inbound, &mut buffer).await;
+        assert!(matches!(read, Ok(0) | Err(_)));
+        let err = proxy.failure("closed".into()).to_string();
+        assert!(err.starts_with(&format!("Connecting to {name}: ")), "{err}");
+    }
+
+    #[tokio::test]
+    async fn stops_listening_after_limit() {
+        let (listener, target) = target().await;
+        let settings = ProxySettings {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let proxy = AddressProxy::start(vec![target], settings).await.unwrap();
+
+        for _ in 0..2 {
+            let _inbound = TcpStream::connect(proxy.address()).await.unwrap();
+            listener.accept().await.unwrap();
+        }
+        time::sleep(Duration::from_millis(50)).await;
+        assert!(TcpStream::connect(proxy.address()).await.is_err());
+        let err = proxy.failure("refused".into()).to_string();
+        assert!(err.starts_with("The client did not reach the node it needs within 2 connections"));
+    }
+}
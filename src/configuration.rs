@@ -0,0 +1,494 @@
+use std::{error::Error, time::Duration};
+
+use serde_json::{Map, Value};
+
+use crate::environment_builder::{self, AddressMapping, Endpoint, HostSelection};
+
+pub type ConfigResult<T> = Result<T, Box<dyn Error>>;
+
+pub const REDACTED: &str = "***";
+
+/// A JSON object with its path in the document, used for precise error messages.
+pub struct Section<'a> {
+    path: String,
+    map: &'a Map<String, Value>,
+}
+
+impl<'a> Section<'a> {
+    pub fn root(value: &'a Value) -> ConfigResult<Self> {
+        let map = value
+            .as_object()
+            .ok_or("Configuration must be a JSON object")?;
+        Ok(Self {
+            path: String::new(),
+            map,
+        })
+    }
+
+    fn key_path(&self, key: &str) -> String {
+        if self.path.is_empty() {
+            key.to_owned()
+        } else {
+            format!("{}.{}", self.path, key)
+        }
+    }
+
+    pub fn error(&self, key: &str, message: &str) -> Box<dyn Error> {
+        format!("Configuration '{}': {}", self.key_path(key), message).into()
+    }
+
+    /// Fails on the first key that is not in `allowed`.
+    pub fn check_keys(&self, allowed: &[&str]) -> ConfigResult<()> {
+        match self.map.keys().find(|x| !allowed.contains(&x.as_str())) {
+            Some(key) => Err(self.error(
+                key,
+                &format!("unknown key, expected one of: {}", allowed.join(", ")),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    pub fn value(&self, key: &str) -> Option<&'a Value> {
+        self.map.get(key).filter(|x| !x.is_null())
+    }
+
+    pub fn section(&self, key: &str) -> ConfigResult<Option<Section<'a>>> {
+        match self.value(key) {
+            Some(Value::Object(map)) => Ok(Some(Section {
+                path: self.key_path(key),
+                map,
+            })),
+            Some(_) => Err(self.error(key, "object expected")),
+            None => Ok(None),
+        }
+    }
+
+    pub fn string(&self, key: &str) -> ConfigResult<Option<String>> {
+        match self.value(key) {
+            Some(Value::String(x)) => Ok(Some(x.clone())),
+            Some(_) => Err(self.error(key, "string expected")),
+            None => Ok(None),
+        }
+    }
+
+    pub fn bool(&self, key: &str) -> ConfigResult<Option<bool>> {
+        match self.value(key) {
+            Some(Value::Bool(x)) => Ok(Some(*x)),
+            Some(_) => Err(self.error(key, "boolean expected")),
+            None => Ok(None),
+        }
+    }
+
+    /// Accepts both JSON numbers and numeric strings.
+    pub fn int<T: TryFrom<u64>>(&self, key: &str) -> ConfigResult<Option<T>> {
+        let Some(value) = self.value(key) else {
+            return Ok(None);
+        };
+        let value = match value {
+            Value::Number(x) => x.as_u64(),
+            Value::String(x) => x.parse().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| self.error(key, "non-negative integer expected"))?;
+        T::try_from(value)
+            .map(Some)
+            .map_err(|_| self.error(key, &format!("{value} is out of range")))
+    }
+
+    pub fn sections(&self, key: &str) -> ConfigResult<Option<Vec<Section<'a>>>> {
+        match self.value(key) {
+            Some(Value::Array(items)) => items
+                .iter()
+                .enumerate()
+                .map(|(i, x)| match x {
+                    Value::Object(map) => Ok(Section {
+                        path: format!("{}[{}]", self.key_path(key), i),
+                        map,
+                    }),
+                    _ => Err(self.error(key, "array of objects expected")),
+                })
+                .collect::<ConfigResult<Vec<_>>>()
+                .map(Some),
+            Some(_) => Err(self.error(key, "array of objects expected")),
+            None => Ok(None),
+        }
+    }
+
+    pub fn strings(&self, key: &str) -> ConfigResult<Option<Vec<String>>> {
+        match self.value(key) {
+            Some(Value::Array(items)) => items
+                .iter()
+                .map(|x| x.as_str().map(str::to_owned))
+                .collect::<Option<Vec<_>>>()
+                .map(Some)
+                .ok_or_else(|| self.error(key, "array of strings expected")),
+            Some(_) => Err(self.error(key, "array of strings expected")),
+            None => Ok(None),
+        }
+    }
+}
+
+pub fn parse(text: &str) -> ConfigResult<Value> {
+    serde_json::from_str(text).map_err(|e| format!("Invalid configuration JSON: {e}").into())
+}
+
+/// Applies the `environment` and `tls` sections, nothing is changed if any value is invalid.
+pub fn configure_environment(
+    builder: &mut environment_builder::Builder,
+    root: &Section,
+) -> ConfigResult<()> {
+    apply_environment(&mut environment_builder::Builder::new(), root)?;
+    apply_environment(builder, root)
+}
+
+fn apply_environment(
+    builder: &mut environment_builder::Builder,
+    root: &Section,
+) -> ConfigResult<()> {
+    if let Some(section) = root.section("environment")? {
+        section.check_keys(&[
+            "uri",
+            "host",
+            "hosts",
+            "hostSelection",
+            "port",
+            "username",
+            "password",
+            "virtualHost",
+            "heartbeat",
+            "loadBalancerMode",
+            "addressMappings",
+            "connectTimeout",
+            "retryAttempts",
+            "retryBackoff",
+        ])?;
+
+        if let Some(uri) = section.string("uri")? {
+            builder
+                .set_uri(&uri)
+                .map_err(|e| section.error("uri", &e.to_string()))?;
+        }
+
+        let host = section.string("host")?;
+        let hosts = section.strings("hosts")?;
+        let hosts = match (host, hosts) {
+            (Some(_), Some(_)) => {
+                return Err(section.error("hosts", "cannot be used together with 'host'"))
+            }
+            (Some(host), None) => Some(vec![Endpoint { host, port: None }]),
+            (None, Some(hosts)) => Some(
+                hosts
+                    .iter()
+                    .map(|x| environment_builder::parse_endpoint(x))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| section.error("hosts", &e.to_string()))?,
+            ),
+            (None, None) => None,
+        };
+        let host_selection = match section.string("hostSelection")?.as_deref() {
+            None => None,
+            Some("ordered") => Some(HostSelection::Ordered),
+            Some("random") => Some(HostSelection::Random),
+            Some(_) => return Err(section.error("hostSelection", "expected 'ordered' or 'random'")),
+        };
+        let port = section.int("port")?;
+        let username = section.string("username")?;
+        let password = section.string("password")?;
+        let virtual_host = section.string("virtualHost")?;
+        let heartbeat = section.int("heartbeat")?;
+        let load_balancer_mode = section.bool("loadBalancerMode")?;
+        let connect_timeout = section.int::<u64>("connectTimeout")?;
+        let retry_attempts = section.int::<u32>("retryAttempts")?;
+        if retry_attempts == Some(0) {
+            return Err(section.error("retryAttempts", "must be at least 1"));
+        }
+        let retry_backoff = section.int::<u64>("retryBackoff")?;
+        let address_mappings = match section.sections("addressMappings")? {
+            Some(items) => Some(
+                items
+                    .iter()
+                    .map(parse_address_mapping)
+                    .collect::<ConfigResult<Vec<_>>>()?,
+            ),
+            None => None,
+        };
+        if let Some(mappings) = &address_mappings {
+            environment_builder::validate_address_mappings(mappings)
+                .map_err(|e| section.error("addressMappings", &e))?;
+        }
+
+        builder.set_environment(|x| {
+            x.hosts = hosts.unwrap_or(std::mem::take(&mut x.hosts));
+            x.host_selection = host_selection.unwrap_or(x.host_selection);
+            x.port = port.or(x.port);
+            x.username = username.or(x.username.take());
+            x.password = password.or(x.password.take());
+            x.virtual_host = virtual_host.or(x.virtual_host.take());
+            x.heartbeat = heartbeat.or(x.heartbeat);
+            x.load_balancer_mode = load_balancer_mode.or(x.load_balancer_mode);
+            if let Some(timeout) = connect_timeout {
+                x.connect_policy.timeout = (timeout > 0).then(|| Duration::from_millis(timeout));
+            }
+            if let Some(attempts) = retry_attempts {
+                x.connect_policy.attempts = attempts;
+            }
+            if let Some(backoff) = retry_backoff {
+                x.connect_policy.backoff = Duration::from_millis(backoff);
+            }
+            x.address_mappings =
+                address_mappings.unwrap_or(std::mem::take(&mut x.address_mappings));
+        })?;
+    }
+
+    if let Some(section) = root.section("tls")? {
+        section.check_keys(&[
+            "enabled",
+            "clientCertificatePath",
+            "clientPrivateKeyPath",
+            "rootCertificatesPath",
+            "useSystemRoots",
+            "insecure",
+            "serverName",
+        ])?;
+
+        let enabled = section.bool("enabled")?;
+        let client_certificate_path = section.string("clientCertificatePath")?;
+        let client_private_key_path = section.string("clientPrivateKeyPath")?;
+        if client_certificate_path.is_some() != client_private_key_path.is_some() {
+            return Err(section.error(
+                "clientPrivateKeyPath",
+                "must be set together with 'clientCertificatePath'",
+            ));
+        }
+        let server_certificate_path = section.string("rootCertificatesPath")?;
+        let use_system_roots = section.bool("useSystemRoots")?;
+        let insecure = section.bool("insecure")?;
+        let server_name = section.string("serverName")?;
+
+        builder.set_tls(|x| {
+            if let Some(enabled) = enabled {
+                x.enabled = Some(enabled);
+            }
+            if let Some(path) = client_certificate_path {
+                x.client_certificate_path = path;
+            }
+            if let Some(path) = client_private_key_path {
+                x.client_private_key_path = path;
+            }
+            if let Some(path) = server_certificate_path {
+                x.server_certificate_path = path;
+            }
+            if let Some(use_system_roots) = use_system_roots {
+                x.use_system_roots = use_system_roots;
+            }
+            if let Some(insecure) = insecure {
+                x.insecure = insecure;
+            }
+            if let Some(server_name) = server_name {
+                x.server_name = Some(server_name).filter(|x| !x.is_empty());
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+fn parse_address_mapping(section: &Section) -> ConfigResult<AddressMapping> {
+    section.check_keys(&["advertisedHost", "advertisedPort", "realHost", "realPort"])?;
+    let required = |key: &str| section.error(key, "required");
+    Ok(AddressMapping {
+        advertised_host: section
+            .string("advertisedHost")?
+            .ok_or_else(|| required("advertisedHost"))?,
+        advertised_port: section
+            .int("advertisedPort")?
+            .ok_or_else(|| required("advertisedPort"))?,
+        real_host: section
+            .string("realHost")?
+            .ok_or_else(|| required("realHost"))?,
+        real_port: section
+            .int("realPort")?
+            .ok_or_else(|| required("realPort"))?,
+    })
+}
+
+/// Returns the `environment` and `tls` sections, secrets are redacted.
+pub fn export_environment(builder: &environment_builder::Builder, root: &mut Map<String, Value>) {
+    let props = builder.environment_properties();
+    let mut environment = Map::new();
+    let hosts = props
+        .hosts
+        .iter()
+        .map(|x| match x.port {
+            Some(port) => format!("{}:{}", x.host, port).into(),
+            None => x.host.clone().into(),
+        })
+        .collect::<Vec<Value>>();
+    environment.insert("hosts".into(), hosts.into());
+    let host_selection = match props.host_selection {
+        HostSelection::Ordered => "ordered",
+        HostSelection::Random => "random",
+    };
+    environment.insert("hostSelection".into(), host_selection.into());
+    environment.insert("port".into(), props.port.into());
+    environment.insert("username".into(), props.username.clone().into());
+    environment.insert(
+        "password".into(),
+        props.password.as_ref().map(|_| REDACTED).into(),
+    );
+    environment.insert("virtualHost".into(), props.virtual_host.clone().into());
+    environment.insert("heartbeat".into(), props.heartbeat.into());
+    environment.insert("loadBalancerMode".into(), props.load_balancer_mode.into());
+    let policy = &props.connect_policy;
+    environment.insert(
+        "connectTimeout".into(),
+        policy.timeout.map(|x| x.as_millis() as u64).into(),
+    );
+    environment.insert("retryAttempts".into(), policy.attempts.into());
+    environment.insert(
+        "retryBackoff".into(),
+        (policy.backoff.as_millis() as u64).into(),
+    );
+    let address_mappings = props
+        .address_mappings
+        .iter()
+        .map(|x| {
+            let mut mapping = Map::new();
+            mapping.insert("advertisedHost".into(), x.advertised_host.clone().into());
+            mapping.insert("advertisedPort".into(), x.advertised_port.into());
+            mapping.insert("realHost".into(), x.real_host.clone().into());
+            mapping.insert("realPort".into(), x.real_port.into());
+            Value::Object(mapping)
+        })
+        .collect::<Vec<_>>();
+    environment.insert("addressMappings".into(), address_mappings.into());
+    root.insert("environment".into(), environment.into());
+
+    if let Some(props) = builder.tls_properties() {
+        let path = |x: &String| match x.is_empty() {
+            true => Value::Null,
+            false => x.as_str().into(),
+        };
+        let mut tls = Map::new();
+        tls.insert("enabled".into(), props.is_enabled().into());
+        tls.insert(
+            "clientCertificatePath".into(),
+            path(&props.client_certificate_path),
+        );
+        tls.insert(
+            "clientPrivateKeyPath".into(),
+            path(&props.client_private_key_path),
+        );
+        tls.insert(
+            "rootCertificatesPath".into(),
+            path(&props.server_certificate_path),
+        );
+        tls.insert("useSystemRoots".into(), props.use_system_roots.into());
+        tls.insert("insecure".into(), props.insecure.into());
+        tls.insert("serverName".into(), props.server_name.clone().into());
+        root.insert("tls".into(), tls.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn error(result: ConfigResult<impl Sized>) -> String {
+        result.err().unwrap().to_string()
+    }
+
+    #[test]
+    fn values_are_read_with_their_path() {
+        let value = json!({
+            "a": {"name": "x", "flag": true, "port": "5552", "empty": null},
+            "items": [{"port": 70000}],
+            "hosts": ["h1", "h2"]
+        });
+        let root = Section::root(&value).unwrap();
+        let section = root.section("a").unwrap().unwrap();
+        assert_eq!(section.string("name").unwrap().as_deref(), Some("x"));
+        assert_eq!(section.bool("flag").unwrap(), Some(true));
+        assert_eq!(section.int::<u16>("port").unwrap(), Some(5552));
+        assert_eq!(section.string("empty").unwrap(), None);
+        assert_eq!(section.string("missing").unwrap(), None);
+        assert_eq!(
+            root.strings("hosts").unwrap().unwrap(),
+            ["h1".to_owned(), "h2".to_owned()]
+        );
+
+        assert_eq!(
+            error(section.int::<u16>("name")),
+            "Configuration 'a.name': non-negative integer expected"
+        );
+        assert_eq!(
+            error(section.string("flag")),
+            "Configuration 'a.flag': string expected"
+        );
+        let items = root.sections("items").unwrap().unwrap();
+        assert_eq!(
+            error(items[0].int::<u16>("port")),
+            "Configuration 'items[0].port': 70000 is out of range"
+        );
+        assert_eq!(
+            error(root.sections("hosts")),
+            "Configuration 'hosts': array of objects expected"
+        );
+        assert_eq!(
+            error(section.check_keys(&["name", "flag", "empty"])),
+            "Configuration 'a.port': unknown key, expected one of: name, flag, empty"
+        );
+        assert!(Section::root(&json!([])).is_err());
+    }
+
+    #[test]
+    fn configuration_is_applied_atomically() {
+        let mut builder = environment_builder::Builder::new();
+        let value = json!({"environment": {"username": "user", "port": 70000}});
+        let root = Section::root(&value).unwrap();
+        assert_eq!(
+            error(configure_environment(&mut builder, &root)),
+            "Configuration 'environment.port': 70000 is out of range"
+        );
+        assert_eq!(builder.environment_properties().username, None);
+    }
+
+    #[test]
+    fn exported_configuration_is_accepted_back() {
+        let value = json!({
+            "environment": {
+                "hosts": ["h1:5553", "h2"],
+                "hostSelection": "random",
+                "username": "user",
+                "password": "secret",
+                "connectTimeout": 5000,
+                "addressMappings": [
+                    {"advertisedHost": "node", "advertisedPort": 5552, "realHost": "localhost", "realPort": 6000}
+                ]
+            },
+            "tls": {"rootCertificatesPath": "ca.pem", "insecure": true, "serverName": "rabbit"}
+        });
+        let mut builder = environment_builder::Builder::new();
+        configure_environment(&mut builder, &Section::root(&value).unwrap()).unwrap();
+
+        let mut exported = Map::new();
+        export_environment(&builder, &mut exported);
+        let environment = &exported["environment"];
+        assert_eq!(environment["hosts"], json!(["h1:5553", "h2"]));
+        assert_eq!(environment["password"], REDACTED);
+        assert_eq!(environment["connectTimeout"], 5000);
+        assert_eq!(exported["tls"]["enabled"], true);
+        assert_eq!(exported["tls"]["rootCertificatesPath"], "ca.pem");
+        assert_eq!(exported["tls"]["serverName"], "rabbit");
+
+        // the redacted password is the only value that changes on a round trip
+        let exported = Value::Object(exported);
+        let mut again = environment_builder::Builder::new();
+        configure_environment(&mut again, &Section::root(&exported).unwrap()).unwrap();
+        let mut exported_again = Map::new();
+        export_environment(&again, &mut exported_again);
+        assert_eq!(Value::Object(exported_again), exported);
+    }
+}
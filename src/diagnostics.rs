@@ -0,0 +1,379 @@
+use std::{
+    error::Error,
+    future::Future,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::DateTime;
+use rabbitmq_stream_client::{error::ClientError, types::ResponseCode, Client};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{aws_lc_rs, verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    ClientConfig, DigitallySignedStruct, SignatureScheme,
+};
+use serde_json::{Map, Value};
+use tokio::{
+    net::{lookup_host, TcpStream},
+    time,
+};
+use tokio_rustls::TlsConnector;
+
+use crate::{
+    address_proxy::{AddressProxy, ProxySettings, Target},
+    environment_builder,
+};
+
+/// Used for every step when no connect timeout is set, so that `Diagnose` never hangs.
+const DEFAULT_STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One endpoint report: the steps up to and including the first failed one.
+struct Report {
+    steps: Vec<Value>,
+    ok: bool,
+}
+
+impl Report {
+    fn new() -> Self {
+        Self {
+            steps: Vec::new(),
+            ok: true,
+        }
+    }
+
+    fn success(&mut self, step: &str, started: Instant, details: Map<String, Value>) {
+        self.push(step, started, details, None);
+    }
+
+    fn failure(&mut self, step: &str, started: Instant, error: impl ToString) {
+        self.push(step, started, Map::new(), Some(error.to_string()));
+    }
+
+    fn push(
+        &mut self,
+        step: &str,
+        started: Instant,
+        details: Map<String, Value>,
+        error: Option<String>,
+    ) {
+        let mut value = Map::new();
+        value.insert("step".into(), step.into());
+        value.insert("ok".into(), error.is_none().into());
+        value.insert(
+            "durationMs".into(),
+            (started.elapsed().as_millis() as u64).into(),
+        );
+        value.extend(details);
+        if let Some(error) = error {
+            value.insert("error".into(), error.into());
+            self.ok = false;
+        }
+        self.steps.push(value.into());
+    }
+}
+
+/// Checks every endpoint step by step: DNS, TCP, TLS handshake, authentication,
+/// virtual host access and, if given, the stream existence.
+pub async fn diagnose(builder: &environment_builder::Builder, stream: Option<&str>) -> Value {
+    let props = builder.environment_properties();
+    let timeout = props.connect_policy.timeout.unwrap_or(DEFAULT_STEP_TIMEOUT);
+
+    let mut endpoints = Vec::new();
+    let mut ok = true;
+    for (host, port) in props.endpoint_addresses() {
+        let report = diagnose_endpoint(builder, &host, port, stream, timeout).await;
+        ok &= report.ok;
+
+        let mut endpoint = Map::new();
+        endpoint.insert("endpoint".into(), format!("{host}:{port}").into());
+        endpoint.insert("ok".into(), report.ok.into());
+        endpoint.insert("steps".into(), report.steps.into());
+        endpoints.push(Value::Object(endpoint));
+    }
+
+    let mut result = Map::new();
+    result.insert("ok".into(), ok.into());
+    result.insert("warnings".into(), builder.warnings().into());
+    result.insert("endpoints".into(), endpoints.into());
+    result.into()
+}
+
+async fn diagnose_endpoint(
+    builder: &environment_builder::Builder,
+    host: &str,
+    port: u16,
+    stream: Option<&str>,
+    timeout: Duration,
+) -> Report {
+    let mut report = Report::new();
+
+    let started = Instant::now();
+    let addresses = match with_timeout(timeout, lookup_host((host, port))).await {
+        Ok(addresses) => addresses.collect::<Vec<SocketAddr>>(),
+        Err(err) => {
+            report.failure("dns", started, err);
+            return report;
+        }
+    };
+    let mut details = Map::new();
+    let list = addresses.iter().map(|x| x.ip().to_string().into());
+    details.insert("addresses".into(), Value::Array(list.collect()));
+    report.success("dns", started, details);
+
+    let started = Instant::now();
+    let mut tcp = None;
+    let mut last_error = None;
+    for address in &addresses {
+        match with_timeout(timeout, TcpStream::connect(address)).await {
+            Ok(x) => {
+                tcp = Some((x, address));
+                break;
+            }
+            Err(err) => last_error = Some(format!("{address}: {err}")),
+        }
+    }
+    let Some((tcp, address)) = tcp else {
+        let error = last_error.unwrap_or_else(|| "No addresses".to_owned());
+        report.failure("tcp", started, error);
+        return report;
+    };
+    let mut details = Map::new();
+    details.insert("address".into(), address.to_string().into());
+    report.success("tcp", started, details);
+
+    if builder.tls_enabled() {
+        let started = Instant::now();
+        let server_name = builder.tls_server_name().unwrap_or(host);
+        match with_timeout(timeout, tls_handshake(tcp, server_name)).await {
+            Ok(details) => report.success("tls", started, details),
+            Err(err) => {
+                report.failure("tls", started, err);
+                return report;
+            }
+        }
+    } else {
+        drop(tcp);
+    }
+
+    // the client performs TLS verification through the proxy, authentication and opens
+    // the virtual host at once, the failed step is recognized by the error
+    let started = Instant::now();
+    let tls = match builder.rustls_configuration() {
+        Ok(x) => x.map(Arc::new),
+        Err(err) => {
+            report.failure("tlsVerification", started, err);
+            return report;
+        }
+    };
+    let props = builder.environment_properties();
+    let mut proxy = None;
+    let options = match tls {
+        Some(tls) => {
+            let target = Target {
+                address: (host.to_owned(), port),
+                server_name: host.to_owned(),
+            };
+            let settings = ProxySettings {
+                tls: Some(tls),
+                server_name: builder.tls_server_name().map(str::to_owned),
+                timeout: Some(timeout),
+                limit: Some(1),
+            };
+            let address = match AddressProxy::start(vec![target], settings).await {
+                Ok(x) => proxy.insert(x).address(),
+                Err(err) => {
+                    report.failure("connection", started, err);
+                    return report;
+                }
+            };
+            props.client_options(&address.ip().to_string(), address.port())
+        }
+        None => props.client_options(host, port),
+    };
+    let client = match time::timeout(timeout, Client::connect(options)).await {
+        Ok(Ok(client)) => client,
+        Ok(Err(err)) => {
+            let step = match &err {
+                ClientError::RequestError(ResponseCode::VirtualHostAccessFailure) => {
+                    report.success("authentication", started, Map::new());
+                    "virtualHost"
+                }
+                ClientError::RequestError(_) => "authentication",
+                // the handshake has already succeeded, only the verification is left
+                _ if proxy.is_some() => "tlsVerification",
+                _ => "connection",
+            };
+            let err = match &proxy {
+                Some(proxy) => proxy.failure(err.into()),
+                None => err.into(),
+            };
+            report.failure(step, started, err);
+            return report;
+        }
+        Err(_) => {
+            report.failure("connection", started, timeout_error(timeout));
+            return report;
+        }
+    };
+    if builder.tls_enabled() {
+        report.success("tlsVerification", started, Map::new());
+    }
+    report.success("authentication", started, Map::new());
+    let mut details = Map::new();
+    let server_properties = client.server_properties().await;
+    if let Some(version) = server_properties.get("version") {
+        details.insert("serverVersion".into(), version.as_str().into());
+    }
+    report.success("virtualHost", started, details);
+
+    if let Some(stream) = stream {
+        let started = Instant::now();
+        match with_timeout(timeout, client.metadata(vec![stream.to_owned()])).await {
+            Ok(metadata) => match metadata.get(stream) {
+                Some(metadata) if metadata.response_code == ResponseCode::Ok => {
+                    let mut details = Map::new();
+                    let leader = &metadata.leader;
+                    let replicas = metadata
+                        .replicas
+                        .iter()
+                        .map(|x| format!("{}:{}", x.host, x.port).into());
+                    details.insert("stream".into(), stream.into());
+                    details.insert(
+                        "leader".into(),
+                        format!("{}:{}", leader.host, leader.port).into(),
+                    );
+                    details.insert("replicas".into(), Value::Array(replicas.collect()));
+                    report.success("stream", started, details);
+                }
+                Some(metadata) => report.failure(
+                    "stream",
+                    started,
+                    format!("Stream '{stream}': {:?}", metadata.response_code),
+                ),
+                None => report.failure(
+                    "stream",
+                    started,
+                    format!("Stream '{stream}' does not exist"),
+                ),
+            },
+            Err(err) => report.failure("stream", started, err),
+        }
+    }
+
+    let _ = client.close().await;
+    report
+}
+
+async fn with_timeout<T, E>(
+    timeout: Duration,
+    future: impl Future<Output = Result<T, E>>,
+) -> Result<T, Box<dyn Error>>
+where
+    E: Into<Box<dyn Error>>,
+{
+    match time::timeout(timeout, future).await {
+        Ok(result) => result.map_err(Into::into),
+        Err(_) => Err(timeout_error(timeout)),
+    }
+}
+
+fn timeout_error(timeout: Duration) -> Box<dyn Error> {
+    format!("Timed out after {} ms", timeout.as_millis()).into()
+}
+
+/// Completes a TLS handshake without verification, only to describe the server certificate,
+/// the certificate is verified later by the client itself.
+async fn tls_handshake(
+    tcp: TcpStream,
+    server_name: &str,
+) -> Result<Map<String, Value>, Box<dyn Error>> {
+    let verifier = Arc::new(CapturingVerifier::default());
+    let config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(verifier.clone())
+        .with_no_client_auth();
+    let server_name = ServerName::try_from(server_name.to_owned())?;
+    let connection = TlsConnector::from(Arc::new(config))
+        .connect(server_name, tcp)
+        .await?;
+
+    let mut details = Map::new();
+    let (_, session) = connection.get_ref();
+    if let Some(version) = session.protocol_version() {
+        details.insert("protocol".into(), format!("{version:?}").into());
+    }
+    let certificate = verifier.certificate.lock().unwrap().take();
+    let certificate = certificate.ok_or("Server did not send a certificate")?;
+    let (_, certificate) = x509_parser::parse_x509_certificate(&certificate)
+        .map_err(|e| format!("Invalid server certificate: {e}"))?;
+    let validity = certificate.validity();
+    let time = |x: i64| DateTime::from_timestamp(x, 0).map(|x| x.to_rfc3339());
+    details.insert("subject".into(), certificate.subject().to_string().into());
+    details.insert("issuer".into(), certificate.issuer().to_string().into());
+    details.insert(
+        "notBefore".into(),
+        time(validity.not_before.timestamp()).into(),
+    );
+    details.insert(
+        "notAfter".into(),
+        time(validity.not_after.timestamp()).into(),
+    );
+    details.insert("expired".into(), (!validity.is_valid()).into());
+    Ok(details)
+}
+
+/// Accepts any server certificate and keeps the end entity one.
+#[derive(Debug, Default)]
+pub struct CapturingVerifier {
+    certificate: Mutex<Option<Vec<u8>>>,
+}
+
+impl CapturingVerifier {
+    fn provider() -> Arc<CryptoProvider> {
+        CryptoProvider::get_default()
+            .cloned()
+            .unwrap_or_else(|| Arc::new(aws_lc_rs::default_provider()))
+    }
+}
+
+impl ServerCertVerifier for CapturingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        *self.certificate.lock().unwrap() = Some(end_entity.to_vec());
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let algorithms = &Self::provider().signature_verification_algorithms;
+        verify_tls12_signature(message, cert, dss, algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let algorithms = &Self::provider().signature_verification_algorithms;
+        verify_tls13_signature(message, cert, dss, algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        Self::provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
@@ -1,6 +1,16 @@
 mod addin_consumer;
 mod addin_producer;
+mod address_proxy;
+mod configuration;
+mod diagnostics;
 mod environment_builder;
+mod message_json;
+mod prefetch;
+mod runtime;
+mod stream_stats;
+mod text_encoding;
+mod time_zone;
+mod tls_data;
 
 use std::{
     ffi::{c_int, c_long, c_void},
@@ -0,0 +1,676 @@
+use std::{error::Error, mem::transmute};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use rabbitmq_stream_client::types::{Delivery, Message, Properties, SimpleValue};
+use serde_json::{Map, Value};
+
+type JsonResult<T> = Result<T, Box<dyn Error>>;
+
+/// Parses a JSON array or NDJSON document into messages.
+///
+/// Each element is an object with the keys `body`, `bodyEncoding`,
+/// `applicationProperties` and `properties`, all of them optional.
+pub fn parse_messages(text: &str) -> JsonResult<Vec<Message>> {
+    let text = text.trim_start_matches('\u{feff}').trim();
+    if text.starts_with('[') {
+        let items: Vec<Value> =
+            serde_json::from_str(text).map_err(|e| format!("Invalid JSON: {e}"))?;
+        items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                parse_message(item).map_err(|e| format!("Message {}: {e}", index + 1).into())
+            })
+            .collect()
+    } else {
+        // messages are numbered as in the array form, the line points into the text
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .enumerate()
+            .map(|(index, (line_index, line))| {
+                let position = format!("Message {} (line {})", index + 1, line_index + 1);
+                let item: Value = serde_json::from_str(line)
+                    .map_err(|e| format!("{position}: invalid JSON: {e}"))?;
+                parse_message(&item).map_err(|e| format!("{position}: {e}").into())
+            })
+            .collect()
+    }
+}
+
+fn parse_message(item: &Value) -> JsonResult<Message> {
+    let item = item.as_object().ok_or("object expected")?;
+
+    let encoding = match item.get("bodyEncoding") {
+        None | Some(Value::Null) => "utf8",
+        Some(Value::String(x)) => x.as_str(),
+        Some(_) => return Err("'bodyEncoding' must be a string".into()),
+    };
+    if !matches!(encoding, "utf8" | "base64" | "json") {
+        return Err(format!("unknown body encoding '{encoding}'").into());
+    }
+
+    let mut builder = Message::builder();
+    for key in item.keys() {
+        match key.as_str() {
+            "body" | "bodyEncoding" | "applicationProperties" | "properties" => {}
+            _ => return Err(format!("unknown key '{key}'").into()),
+        }
+    }
+
+    if let Some(body) = item.get("body") {
+        let data = parse_body(body, encoding)?;
+        if !data.is_empty() {
+            builder = builder.body(data);
+        }
+    }
+
+    if let Some(props) = item.get("applicationProperties") {
+        let props = props
+            .as_object()
+            .ok_or("'applicationProperties' must be an object")?;
+        for (key, value) in props {
+            let value = parse_simple_value(value)
+                .map_err(|e| format!("application property '{key}': {e}"))?;
+            builder = builder
+                .application_properties()
+                .insert(key.as_str(), value)
+                .message_builder();
+        }
+    }
+
+    if let Some(props) = item.get("properties") {
+        let props = props.as_object().ok_or("'properties' must be an object")?;
+        let mut properties = builder.properties();
+        for (key, value) in props {
+            if value.is_null() {
+                continue;
+            }
+            let string = || -> JsonResult<String> {
+                value
+                    .as_str()
+                    .map(str::to_owned)
+                    .ok_or_else(|| format!("property '{key}' must be a string").into())
+            };
+            properties = match key.as_str() {
+                "messageId" => {
+                    match parse_message_id(value).map_err(|e| format!("property '{key}': {e}"))? {
+                        MessageIdValue::String(x) => properties.message_id(x),
+                        MessageIdValue::Ulong(x) => properties.message_id(x),
+                        MessageIdValue::Long(x) => properties.message_id(x),
+                        MessageIdValue::Uuid(x) => properties.message_id(x),
+                        MessageIdValue::Binary(x) => properties.message_id(x),
+                    }
+                }
+                "correlationId" => {
+                    match parse_message_id(value).map_err(|e| format!("property '{key}': {e}"))? {
+                        MessageIdValue::String(x) => properties.correlation_id(x),
+                        MessageIdValue::Ulong(x) => properties.correlation_id(x),
+                        MessageIdValue::Long(x) => properties.correlation_id(x),
+                        MessageIdValue::Uuid(x) => properties.correlation_id(x),
+                        MessageIdValue::Binary(x) => properties.correlation_id(x),
+                    }
+                }
+                "userId" => properties.user_id(string()?.into_bytes()),
+                "to" => properties.to(string()?),
+                "subject" => properties.subject(string()?),
+                "replyTo" => properties.reply_to(string()?),
+                "contentType" => properties.content_type(string()?.as_str()),
+                "contentEncoding" => properties.content_encoding(string()?.as_str()),
+                "absoluteExpiryTime" => properties.absolute_expiry_time(
+                    parse_timestamp(value).map_err(|e| format!("property '{key}': {e}"))?,
+                ),
+                "creationTime" => properties.creation_time(
+                    parse_timestamp(value).map_err(|e| format!("property '{key}': {e}"))?,
+                ),
+                "groupId" => properties.group_id(string()?),
+                "groupSequence" => properties.group_sequence(
+                    value
+                        .as_u64()
+                        .and_then(|x| u32::try_from(x).ok())
+                        .ok_or("property 'groupSequence' must be an unsigned 32-bit integer")?,
+                ),
+                "replyToGroupId" => properties.reply_to_group_id(string()?),
+                _ => return Err(format!("unknown property '{key}'").into()),
+            };
+        }
+        builder = properties.message_builder();
+    }
+
+    Ok(builder.build())
+}
+
+fn parse_body(body: &Value, encoding: &str) -> JsonResult<Vec<u8>> {
+    match (encoding, body) {
+        (_, Value::Null) => Ok(Vec::new()),
+        ("utf8", Value::String(x)) => Ok(x.as_bytes().to_vec()),
+        ("base64", Value::String(x)) => STANDARD
+            .decode(x)
+            .map_err(|e| format!("invalid base64 body: {e}").into()),
+        ("json", Value::String(x)) => Ok(x.as_bytes().to_vec()),
+        ("json", x) => Ok(serde_json::to_vec(x)?),
+        _ => Err("'body' must be a string".into()),
+    }
+}
+
+fn parse_simple_value(value: &Value) -> JsonResult<SimpleValue> {
+    let value = match value {
+        Value::Null => SimpleValue::Null,
+        Value::Bool(x) => SimpleValue::Boolean(*x),
+        Value::Number(x) => {
+            if let Some(x) = x.as_i64() {
+                match i32::try_from(x) {
+                    Ok(x) => SimpleValue::Int(x),
+                    Err(_) => SimpleValue::Long(x),
+                }
+            } else if let Some(x) = x.as_u64() {
+                SimpleValue::Ulong(x)
+            } else {
+                SimpleValue::Double(x.as_f64().ok_or("invalid number")?.into())
+            }
+        }
+        Value::String(x) => SimpleValue::String(x.clone()),
+        Value::Object(x) => parse_typed_value(x)?,
+        Value::Array(_) => return Err("arrays are not supported".into()),
+    };
+    Ok(value)
+}
+
+/// Parses a value in the form `{"type": "long", "value": 123}`.
+fn parse_typed_value(value: &Map<String, Value>) -> JsonResult<SimpleValue> {
+    let type_name = value
+        .get("type")
+        .and_then(Value::as_str)
+        .ok_or("typed value must have a string 'type'")?;
+    let inner = value.get("value").ok_or("typed value must have 'value'")?;
+    if let Some(key) = value.keys().find(|x| *x != "type" && *x != "value") {
+        return Err(format!("unknown key '{key}'").into());
+    }
+
+    fn int<T: TryFrom<i64>>(inner: &Value) -> JsonResult<T> {
+        let x = match inner {
+            Value::Number(x) => x.as_i64(),
+            Value::String(x) => x.parse().ok(),
+            _ => None,
+        }
+        .ok_or("integer expected")?;
+        T::try_from(x).map_err(|_| format!("{x} is out of range").into())
+    }
+
+    fn float(inner: &Value) -> JsonResult<f64> {
+        match inner {
+            Value::Number(x) => x.as_f64(),
+            Value::String(x) => x.parse().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| "number expected".into())
+    }
+
+    fn string(inner: &Value) -> JsonResult<String> {
+        inner
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| "string expected".into())
+    }
+
+    let value = match type_name {
+        "bool" | "boolean" => SimpleValue::Boolean(inner.as_bool().ok_or("boolean expected")?),
+        "byte" => SimpleValue::Byte(int(inner)?),
+        "short" => SimpleValue::Short(int(inner)?),
+        "int" => SimpleValue::Int(int(inner)?),
+        "long" => SimpleValue::Long(int(inner)?),
+        "ubyte" => SimpleValue::Ubyte(int(inner)?),
+        "ushort" => SimpleValue::Ushort(int(inner)?),
+        "uint" => SimpleValue::Uint(int(inner)?),
+        "ulong" => SimpleValue::Ulong(
+            match inner {
+                Value::Number(x) => x.as_u64(),
+                Value::String(x) => x.parse().ok(),
+                _ => None,
+            }
+            .ok_or("unsigned integer expected")?,
+        ),
+        "float" => SimpleValue::Float((float(inner)? as f32).into()),
+        "double" => SimpleValue::Double(float(inner)?.into()),
+        "string" => SimpleValue::String(string(inner)?),
+        "symbol" => SimpleValue::Symbol(string(inner)?.as_str().into()),
+        "timestamp" => SimpleValue::Timestamp(parse_timestamp(inner)?.into()),
+        "uuid" => SimpleValue::Uuid(
+            uuid::Uuid::parse_str(&string(inner)?).map_err(|e| format!("invalid uuid: {e}"))?,
+        ),
+        "binary" => SimpleValue::Binary(
+            STANDARD
+                .decode(string(inner)?)
+                .map_err(|e| format!("invalid base64: {e}"))?,
+        ),
+        x => return Err(format!("unknown type '{x}'").into()),
+    };
+    Ok(value)
+}
+
+/// One of the AMQP message id types, `MessageId` is not exported by the client.
+enum MessageIdValue {
+    String(String),
+    Ulong(u64),
+    Long(i64),
+    Uuid(uuid::Uuid),
+    Binary(Vec<u8>),
+}
+
+/// Accepts a string, an integer (`ulong`, `long` if negative) or a typed value.
+fn parse_message_id(value: &Value) -> JsonResult<MessageIdValue> {
+    let simple_value = match value {
+        Value::String(x) => return Ok(MessageIdValue::String(x.clone())),
+        Value::Number(x) => {
+            if let Some(x) = x.as_u64() {
+                return Ok(MessageIdValue::Ulong(x));
+            }
+            return Ok(MessageIdValue::Long(x.as_i64().ok_or("integer expected")?));
+        }
+        Value::Object(x) => parse_typed_value(x)?,
+        _ => return Err("string, integer or typed value expected".into()),
+    };
+    match simple_value {
+        SimpleValue::String(x) => Ok(MessageIdValue::String(x)),
+        SimpleValue::Ulong(x) => Ok(MessageIdValue::Ulong(x)),
+        SimpleValue::Long(x) => Ok(MessageIdValue::Long(x)),
+        SimpleValue::Uuid(x) => Ok(MessageIdValue::Uuid(x)),
+        SimpleValue::Binary(x) => Ok(MessageIdValue::Binary(x)),
+        _ => Err("type must be one of 'string', 'ulong', 'long', 'uuid', 'binary'".into()),
+    }
+}
+
+/// Accepts either an RFC 3339 string or Unix time in milliseconds.
+fn parse_timestamp(value: &Value) -> JsonResult<DateTime<Utc>> {
+    match value {
+        Value::String(x) => Ok(DateTime::parse_from_rfc3339(x)
+            .map_err(|e| format!("invalid timestamp '{x}': {e}"))?
+            .with_timezone(&Utc)),
+        Value::Number(x) => {
+            let millis = x.as_i64().ok_or("invalid timestamp")?;
+            Utc.timestamp_millis_opt(millis)
+                .single()
+                .ok_or_else(|| format!("invalid timestamp {millis}").into())
+        }
+        _ => Err("timestamp must be a string or a number".into()),
+    }
+}
+
+/// Serializes deliveries into a JSON array, symmetric to [`parse_messages`].
+///
+/// Offsets and 64-bit integers are written as strings, bodies are written as
+/// UTF-8 text when `utf8_bodies` is set and the body is valid UTF-8, otherwise
+/// as base64.
+pub fn deliveries_to_json(deliveries: &[Delivery], utf8_bodies: bool) -> JsonResult<String> {
+    let items = deliveries
+        .iter()
+        .map(|delivery| message_to_json(delivery.offset(), delivery.message(), utf8_bodies))
+        .collect::<Vec<_>>();
+    Ok(serde_json::to_string(&items)?)
+}
+
+fn message_to_json(offset: u64, message: &Message, utf8_bodies: bool) -> Value {
+    let mut item = Map::new();
+    item.insert("offset".into(), offset.to_string().into());
+
+    if let Some(data) = message.data() {
+        match std::str::from_utf8(data) {
+            Ok(text) if utf8_bodies => {
+                item.insert("body".into(), text.into());
+                item.insert("bodyEncoding".into(), "utf8".into());
+            }
+            _ => {
+                item.insert("body".into(), STANDARD.encode(data).into());
+                item.insert("bodyEncoding".into(), "base64".into());
+            }
+        }
+    }
+
+    if let Some(props) = message.application_properties() {
+        let props = props
+            .iter()
+            .map(|(key, value)| (key.clone(), simple_value_to_json(value)))
+            .collect::<Map<_, _>>();
+        item.insert("applicationProperties".into(), props.into());
+    }
+
+    if let Some(props) = message.properties() {
+        item.insert("properties".into(), properties_to_json(props).into());
+    }
+
+    item.into()
+}
+
+fn typed(type_name: &str, value: impl Into<Value>) -> Value {
+    let mut item = Map::new();
+    item.insert("type".into(), type_name.into());
+    item.insert("value".into(), value.into());
+    item.into()
+}
+
+/// `Timestamp` is not exported by the client, so it is passed through `SimpleValue`.
+pub fn timestamp_to_datetime(timestamp: impl Into<SimpleValue>) -> Option<DateTime<Utc>> {
+    match timestamp.into() {
+        SimpleValue::Timestamp(x) => {
+            let datetime: DateTime<Utc> = unsafe { transmute(x) };
+            Some(datetime)
+        }
+        _ => None,
+    }
+}
+
+fn timestamp_to_json(timestamp: impl Into<SimpleValue>) -> Value {
+    match timestamp_to_datetime(timestamp) {
+        Some(x) => x.to_rfc3339_opts(SecondsFormat::Millis, true).into(),
+        None => Value::Null,
+    }
+}
+
+fn simple_value_to_json(value: &SimpleValue) -> Value {
+    match value {
+        SimpleValue::Null => Value::Null,
+        SimpleValue::Boolean(x) => typed("bool", *x),
+        SimpleValue::Ubyte(x) => typed("ubyte", *x),
+        SimpleValue::Ushort(x) => typed("ushort", *x),
+        SimpleValue::Uint(x) => typed("uint", *x),
+        SimpleValue::Ulong(x) => typed("ulong", x.to_string()),
+        SimpleValue::Byte(x) => typed("byte", *x),
+        SimpleValue::Short(x) => typed("short", *x),
+        SimpleValue::Int(x) => typed("int", *x),
+        SimpleValue::Long(x) => typed("long", x.to_string()),
+        SimpleValue::Float(x) => {
+            let f: f32 = unsafe { transmute(x.clone()) };
+            typed("float", f)
+        }
+        SimpleValue::Double(x) => {
+            let f: f64 = unsafe { transmute(x.clone()) };
+            typed("double", f)
+        }
+        SimpleValue::Char(x) => typed("string", x.to_string()),
+        SimpleValue::Timestamp(x) => typed("timestamp", timestamp_to_json(x.clone())),
+        SimpleValue::Uuid(x) => typed("uuid", x.to_string()),
+        SimpleValue::Binary(x) => typed("binary", STANDARD.encode(x)),
+        SimpleValue::String(x) => typed("string", x.as_str()),
+        SimpleValue::Symbol(x) => typed("symbol", x.as_str()),
+    }
+}
+
+fn properties_to_json(props: &Properties) -> Map<String, Value> {
+    let mut item = Map::new();
+    let mut insert = |key: &str, value: Option<Value>| {
+        if let Some(value) = value {
+            item.insert(key.into(), value);
+        }
+    };
+
+    insert(
+        "messageId",
+        props.message_id.as_ref().map(message_id_to_json),
+    );
+    insert(
+        "userId",
+        props
+            .user_id
+            .as_ref()
+            .map(|x| String::from_utf8_lossy(x).into()),
+    );
+    insert("to", props.to.clone().map(Value::from));
+    insert("subject", props.subject.clone().map(Value::from));
+    insert("replyTo", props.reply_to.clone().map(Value::from));
+    insert(
+        "correlationId",
+        props.correlation_id.as_ref().map(message_id_to_json),
+    );
+    insert(
+        "contentType",
+        props.content_type.as_ref().map(|x| x.as_str().into()),
+    );
+    insert(
+        "contentEncoding",
+        props.content_encoding.as_ref().map(|x| x.as_str().into()),
+    );
+    insert(
+        "absoluteExpiryTime",
+        props
+            .absolute_expiry_time
+            .as_ref()
+            .map(|x| timestamp_to_json(x.clone())),
+    );
+    insert(
+        "creationTime",
+        props
+            .creation_time
+            .as_ref()
+            .map(|x| timestamp_to_json(x.clone())),
+    );
+    insert("groupId", props.group_id.clone().map(Value::from));
+    insert("groupSequence", props.group_sequence.map(Value::from));
+    insert(
+        "replyToGroupId",
+        props.reply_to_group_id.clone().map(Value::from),
+    );
+    item
+}
+
+/// `MessageId` is not exported by the client, so it is matched through its
+/// `TryInto` conversions. Ids of other types than string are typed values,
+/// so that `parse_message_id` restores the type.
+fn message_id_to_json<T>(id: &T) -> Value
+where
+    for<'a> &'a T: TryInto<&'a String> + TryInto<&'a u64> + TryInto<&'a i64>,
+    for<'a> &'a T: TryInto<&'a Vec<u8>> + TryInto<&'a uuid::Uuid>,
+{
+    if let Ok(x) = TryInto::<&String>::try_into(id) {
+        x.as_str().into()
+    } else if let Ok(x) = TryInto::<&u64>::try_into(id) {
+        typed("ulong", x.to_string())
+    } else if let Ok(x) = TryInto::<&i64>::try_into(id) {
+        typed("long", x.to_string())
+    } else if let Ok(x) = TryInto::<&uuid::Uuid>::try_into(id) {
+        typed("uuid", x.to_string())
+    } else if let Ok(x) = TryInto::<&Vec<u8>>::try_into(id) {
+        typed("binary", STANDARD.encode(x))
+    } else {
+        Value::Null
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(text: &str) -> String {
+        parse_messages(text).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn array_and_ndjson_are_parsed() {
+        let array =
+            parse_messages(r#"[{"body": "a"}, {"body": "Yg==", "bodyEncoding": "base64"}]"#)
+                .unwrap();
+        let ndjson = parse_messages(
+            "\u{feff}{\"body\": \"a\"}\n\n{\"body\": \"Yg==\", \"bodyEncoding\": \"base64\"}\n",
+        )
+        .unwrap();
+        for messages in [array, ndjson] {
+            let bodies = messages.iter().map(|x| x.data()).collect::<Vec<_>>();
+            assert_eq!(bodies, [Some(&b"a"[..]), Some(&b"b"[..])]);
+        }
+    }
+
+    #[test]
+    fn json_body_is_serialized() {
+        let messages =
+            parse_messages(r#"[{"body": {"a": 1}, "bodyEncoding": "json"}, {}]"#).unwrap();
+        assert_eq!(messages[0].data(), Some(&br#"{"a":1}"#[..]));
+        assert_eq!(messages[1].data(), None);
+    }
+
+    #[test]
+    fn errors_are_numbered_from_one() {
+        assert_eq!(
+            parse_error(r#"[{}, {"bdy": "a"}]"#),
+            "Message 2: unknown key 'bdy'"
+        );
+        assert_eq!(
+            parse_error("{}\n\n{\"bdy\": \"a\"}"),
+            "Message 2 (line 3): unknown key 'bdy'"
+        );
+        assert!(parse_error("{}\n{").starts_with("Message 2 (line 2): invalid JSON"));
+    }
+
+    #[test]
+    fn unknown_body_encoding_is_rejected_without_body() {
+        assert_eq!(
+            parse_error(r#"[{"bodyEncoding": "hex"}]"#),
+            "Message 1: unknown body encoding 'hex'"
+        );
+        assert_eq!(
+            parse_error(r#"[{"body": 1}]"#),
+            "Message 1: 'body' must be a string"
+        );
+    }
+
+    #[test]
+    fn values_are_checked() {
+        for (text, error) in [
+            (
+                r#"[{"applicationProperties": {"a": {"type": "byte", "value": 300}}}]"#,
+                "Message 1: application property 'a': 300 is out of range",
+            ),
+            (
+                r#"[{"applicationProperties": {"a": [1]}}]"#,
+                "Message 1: application property 'a': arrays are not supported",
+            ),
+            (
+                r#"[{"properties": {"groupSequence": -1}}]"#,
+                "Message 1: property 'groupSequence' must be an unsigned 32-bit integer",
+            ),
+            (
+                r#"[{"properties": {"color": "red"}}]"#,
+                "Message 1: unknown property 'color'",
+            ),
+        ] {
+            assert_eq!(parse_error(text), error);
+        }
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let text = r#"[{
+            "body": "text",
+            "applicationProperties": {
+                "int": 1,
+                "long": 5000000000,
+                "ulong": {"type": "ulong", "value": "18446744073709551615"},
+                "double": 1.5,
+                "bool": true,
+                "string": "s",
+                "null": null,
+                "timestamp": {"type": "timestamp", "value": "2024-01-02T03:04:05.678+03:00"},
+                "uuid": {"type": "uuid", "value": "67e55044-10b1-426f-9247-bb680e5fe0c8"},
+                "binary": {"type": "binary", "value": "AQI="}
+            },
+            "properties": {
+                "messageId": 42,
+                "correlationId": "c-1",
+                "subject": "s",
+                "contentType": "text/plain",
+                "creationTime": 1704153845678,
+                "groupSequence": 7
+            }
+        }]"#;
+        let messages = parse_messages(text).unwrap();
+        let json = message_to_json(3, &messages[0], true);
+        let expected = serde_json::json!({
+            "offset": "3",
+            "body": "text",
+            "bodyEncoding": "utf8",
+            "applicationProperties": {
+                "int": {"type": "int", "value": 1},
+                "long": {"type": "long", "value": "5000000000"},
+                "ulong": {"type": "ulong", "value": "18446744073709551615"},
+                "double": {"type": "double", "value": 1.5},
+                "bool": {"type": "bool", "value": true},
+                "string": {"type": "string", "value": "s"},
+                "null": null,
+                "timestamp": {"type": "timestamp", "value": "2024-01-02T00:04:05.678Z"},
+                "uuid": {"type": "uuid", "value": "67e55044-10b1-426f-9247-bb680e5fe0c8"},
+                "binary": {"type": "binary", "value": "AQI="}
+            },
+            "properties": {
+                "messageId": {"type": "ulong", "value": "42"},
+                "correlationId": "c-1",
+                "subject": "s",
+                "contentType": "text/plain",
+                "creationTime": "2024-01-02T00:04:05.678Z",
+                "groupSequence": 7
+            }
+        });
+        assert_eq!(json, expected);
+
+        // the output is accepted back, apart from the offset
+        let mut json = json;
+        json.as_object_mut().unwrap().remove("offset");
+        let again = parse_messages(&Value::Array(vec![json.clone()]).to_string()).unwrap();
+        let mut json_again = message_to_json(3, &again[0], true);
+        json_again.as_object_mut().unwrap().remove("offset");
+        assert_eq!(json_again, json);
+    }
+
+    #[test]
+    fn message_ids_keep_their_type() {
+        for (id, expected) in [
+            (r#""42""#, r#""42""#),
+            ("42", r#"{"type": "ulong", "value": "42"}"#),
+            ("-42", r#"{"type": "long", "value": "-42"}"#),
+            (
+                r#"{"type": "long", "value": "42"}"#,
+                r#"{"type": "long", "value": "42"}"#,
+            ),
+            (
+                r#"{"type": "uuid", "value": "67e55044-10b1-426f-9247-bb680e5fe0c8"}"#,
+                r#"{"type": "uuid", "value": "67e55044-10b1-426f-9247-bb680e5fe0c8"}"#,
+            ),
+            (
+                r#"{"type": "binary", "value": "AQI="}"#,
+                r#"{"type": "binary", "value": "AQI="}"#,
+            ),
+        ] {
+            let text =
+                format!(r#"[{{"properties": {{"messageId": {id}, "correlationId": {id}}}}}]"#);
+            let json = message_to_json(0, &parse_messages(&text).unwrap()[0], true);
+            let expected: Value = serde_json::from_str(expected).unwrap();
+            assert_eq!(json["properties"]["messageId"], expected, "{id}");
+            assert_eq!(json["properties"]["correlationId"], expected, "{id}");
+
+            // the output is accepted back with the same type
+            let text = serde_json::json!([{"properties": json["properties"]}]).to_string();
+            let again = message_to_json(0, &parse_messages(&text).unwrap()[0], true);
+            assert_eq!(again, json);
+        }
+        assert_eq!(
+            parse_error(r#"[{"properties": {"messageId": {"type": "int", "value": 1}}}]"#),
+            "Message 1: property 'messageId': type must be one of \
+             'string', 'ulong', 'long', 'uuid', 'binary'"
+        );
+        assert_eq!(
+            parse_error(r#"[{"properties": {"correlationId": 1.5}}]"#),
+            "Message 1: property 'correlationId': integer expected"
+        );
+    }
+
+    #[test]
+    fn binary_bodies_fall_back_to_base64() {
+        let messages =
+            parse_messages(r#"[{"body": "/w==", "bodyEncoding": "base64"}, {"body": "a"}]"#)
+                .unwrap();
+        let binary = message_to_json(0, &messages[0], true);
+        assert_eq!(binary["body"], "/w==");
+        assert_eq!(binary["bodyEncoding"], "base64");
+        let text = message_to_json(1, &messages[1], false);
+        assert_eq!(text["body"], "YQ==");
+        assert_eq!(text["bodyEncoding"], "base64");
+    }
+}
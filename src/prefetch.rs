@@ -0,0 +1,193 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    sync::{Arc, Mutex},
+};
+
+use futures::StreamExt;
+use rabbitmq_stream_client::{types::Delivery, Consumer, ConsumerHandle};
+use tokio::{
+    sync::{mpsc, oneshot, Notify},
+    task::JoinHandle,
+    time::{self, Instant},
+};
+
+pub const DEFAULT_MAX_COUNT: usize = 1000;
+pub const DEFAULT_MAX_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy)]
+pub struct PrefetchLimits {
+    pub max_count: usize,
+    pub max_bytes: usize,
+}
+
+impl Default for PrefetchLimits {
+    fn default() -> Self {
+        Self {
+            max_count: DEFAULT_MAX_COUNT,
+            max_bytes: DEFAULT_MAX_BYTES,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    items: VecDeque<Result<Delivery, String>>,
+    bytes: usize,
+    ended: bool,
+}
+
+struct Shared {
+    limits: PrefetchLimits,
+    queue: Mutex<Queue>,
+    /// Signalled by the task when an item is queued or the stream ends.
+    available: Notify,
+    /// Signalled by the reader when an item is taken.
+    space: Notify,
+}
+
+impl Shared {
+    fn is_full(&self) -> bool {
+        let queue = self.queue.lock().unwrap();
+        queue.items.len() >= self.limits.max_count
+            || (!queue.items.is_empty() && queue.bytes >= self.limits.max_bytes)
+    }
+
+    fn push(&self, item: Result<Delivery, String>) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.bytes += item.as_ref().map_or(0, delivery_size);
+        queue.items.push_back(item);
+        self.available.notify_one();
+    }
+
+    fn end(&self) {
+        self.queue.lock().unwrap().ended = true;
+        self.available.notify_one();
+    }
+}
+
+fn delivery_size(delivery: &Delivery) -> usize {
+    delivery.message().data().map_or(0, <[u8]>::len)
+}
+
+enum Command {
+    StoreOffset(u64, oneshot::Sender<Result<(), String>>),
+    Close(oneshot::Sender<()>),
+}
+
+/// Drains the consumer into a bounded queue in the background, so that `next` returns
+/// immediately while messages are buffered.
+///
+/// When the queue is full the task stops reading. The limits cover only this queue: the client
+/// library keeps up to 10000 more messages of any size before it stops granting credit to the
+/// broker.
+pub struct Prefetcher {
+    shared: Arc<Shared>,
+    handle: ConsumerHandle,
+    commands: mpsc::UnboundedSender<Command>,
+    task: JoinHandle<()>,
+}
+
+impl Prefetcher {
+    /// Must be called within a tokio runtime.
+    pub fn start(consumer: Consumer, limits: PrefetchLimits) -> Self {
+        let shared = Arc::new(Shared {
+            limits,
+            queue: Default::default(),
+            available: Notify::new(),
+            space: Notify::new(),
+        });
+        let handle = consumer.handle();
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let task = tokio::spawn(run(consumer, shared.clone(), receiver));
+        Self {
+            shared,
+            handle,
+            commands,
+            task,
+        }
+    }
+
+    /// Waits for the next delivery until `deadline`, `None` on timeout.
+    pub async fn next(&self, deadline: Instant) -> Result<Option<Delivery>, Box<dyn Error>> {
+        loop {
+            let available = self.shared.available.notified();
+            {
+                let mut queue = self.shared.queue.lock().unwrap();
+                if let Some(item) = queue.items.pop_front() {
+                    queue.bytes -= item.as_ref().map_or(0, delivery_size);
+                    self.shared.space.notify_one();
+                    return Ok(Some(item?));
+                }
+                if queue.ended {
+                    return Err("Stream closed".into());
+                }
+            }
+            if time::timeout_at(deadline, available).await.is_err() {
+                return Ok(None);
+            }
+        }
+    }
+
+    pub fn buffered_count(&self) -> usize {
+        self.shared.queue.lock().unwrap().items.len()
+    }
+
+    pub async fn store_offset(&self, offset: u64) -> Result<(), Box<dyn Error>> {
+        let (reply, result) = oneshot::channel();
+        self.commands
+            .send(Command::StoreOffset(offset, reply))
+            .map_err(|_| "Consumer closed")?;
+        result.await.map_err(|_| "Consumer closed")??;
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        futures::executor::block_on(self.handle.is_closed())
+    }
+
+    pub async fn close(&self) {
+        let (reply, result) = oneshot::channel();
+        if self.commands.send(Command::Close(reply)).is_ok() {
+            let _ = result.await;
+        }
+    }
+}
+
+impl Drop for Prefetcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn run(
+    mut consumer: Consumer,
+    shared: Arc<Shared>,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+) {
+    let mut ended = false;
+    loop {
+        let full = shared.is_full();
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(Command::StoreOffset(offset, reply)) => {
+                    let result = consumer.store_offset(offset).await.map_err(|e| e.to_string());
+                    let _ = reply.send(result);
+                }
+                Some(Command::Close(reply)) => {
+                    let _ = consumer.handle().close().await;
+                    let _ = reply.send(());
+                }
+                None => break,
+            },
+            _ = shared.space.notified(), if full && !ended => {}
+            item = consumer.next(), if !full && !ended => match item {
+                Some(item) => shared.push(item.map_err(|e| e.to_string())),
+                None => {
+                    ended = true;
+                    shared.end();
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    error::Error,
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use tokio::runtime::{Builder, Handle, Runtime};
+
+/// 0 means the tokio default, one worker per CPU core.
+static WORKER_THREADS: AtomicUsize = AtomicUsize::new(0);
+static STATE: Mutex<State> = Mutex::new(State {
+    runtime: None,
+    users: 0,
+});
+
+/// Tasks are cancelled at their next await point, blocking ones such as name resolution
+/// may take longer, the worker threads are left to them after this.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct State {
+    runtime: Option<Runtime>,
+    /// Number of `SharedRuntime` instances, that is, of live components.
+    users: usize,
+}
+
+/// The process-wide multi-threaded runtime, created on first use.
+///
+/// Connections are served by its worker threads, so heartbeats, confirms and deliveries
+/// keep being processed while the 1C code is busy between calls to the component.
+/// The runtime is shut down when the last component is dropped, so that no worker threads
+/// are left running when 1C unloads the library, the next component starts a new one.
+pub struct SharedRuntime {
+    handle: OnceLock<Handle>,
+}
+
+impl SharedRuntime {
+    pub fn new() -> Self {
+        STATE.lock().unwrap().users += 1;
+        Self {
+            handle: OnceLock::new(),
+        }
+    }
+
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.handle().block_on(future)
+    }
+
+    pub fn handle(&self) -> &Handle {
+        self.handle.get_or_init(|| {
+            let mut state = STATE.lock().unwrap();
+            state.runtime.get_or_insert_with(build).handle().clone()
+        })
+    }
+}
+
+impl Drop for SharedRuntime {
+    fn drop(&mut self) {
+        let mut state = STATE.lock().unwrap();
+        state.users -= 1;
+        if state.users == 0 {
+            if let Some(runtime) = state.runtime.take() {
+                drop(state);
+                runtime.shutdown_timeout(SHUTDOWN_TIMEOUT);
+            }
+        }
+    }
+}
+
+fn build() -> Runtime {
+    let mut builder = Builder::new_multi_thread();
+    builder.enable_all().thread_name("addin-rabbitmq-stream");
+    match WORKER_THREADS.load(Ordering::Relaxed) {
+        0 => (),
+        count => {
+            builder.worker_threads(count);
+        }
+    }
+    builder.build().expect("Failed to create runtime")
+}
+
+/// Must be called before the runtime is first used.
+pub fn set_worker_threads(count: usize) -> Result<(), Box<dyn Error>> {
+    if STATE.lock().unwrap().runtime.is_some() {
+        return Err(
+            "Runtime is already started, set the number of worker threads before the first Build"
+                .into(),
+        );
+    }
+    WORKER_THREADS.store(count, Ordering::Relaxed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtime_stops_with_last_component() {
+        let first = SharedRuntime::new();
+        let second = SharedRuntime::new();
+        assert_eq!(first.block_on(async { 1 }), 1);
+        assert_eq!(second.block_on(async { 2 }), 2);
+        drop(first);
+        assert!(STATE.lock().unwrap().runtime.is_some());
+        drop(second);
+        assert!(STATE.lock().unwrap().runtime.is_none());
+
+        let third = SharedRuntime::new();
+        assert_eq!(third.block_on(async { 3 }), 3);
+    }
+}
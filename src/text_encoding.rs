@@ -0,0 +1,142 @@
+use std::error::Error;
+
+use addin1c::{ParamValue, Variant};
+use encoding_rs::{UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1251};
+
+#[derive(Clone, Copy)]
+pub enum TextEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Windows1251,
+}
+
+impl TextEncoding {
+    /// Reads the encoding name from a parameter, an omitted parameter means UTF-8.
+    pub fn from_param(param: &Variant) -> Result<Self, Box<dyn Error>> {
+        let name = match param.get() {
+            ParamValue::Empty => return Ok(TextEncoding::Utf8),
+            ParamValue::Str(x) => String::from_utf16_lossy(x),
+            _ => return Err("Encoding must be a string".into()),
+        };
+        Self::parse(&name)
+    }
+
+    /// Parses an encoding name, case insensitive, an empty name means UTF-8.
+    pub fn parse(name: &str) -> Result<Self, Box<dyn Error>> {
+        let name = name.to_lowercase();
+        match name.as_str() {
+            "" | "utf8" | "utf-8" => Ok(TextEncoding::Utf8),
+            "utf16" | "utf-16" | "utf-16le" => Ok(TextEncoding::Utf16Le),
+            "utf-16be" => Ok(TextEncoding::Utf16Be),
+            "windows-1251" | "cp1251" => Ok(TextEncoding::Windows1251),
+            _ => Err(format!("Unknown encoding: {name}").into()),
+        }
+    }
+
+    pub fn encode(self, text: &[u16]) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self {
+            TextEncoding::Utf16Le => Ok(text.iter().flat_map(|x| x.to_le_bytes()).collect()),
+            TextEncoding::Utf16Be => Ok(text.iter().flat_map(|x| x.to_be_bytes()).collect()),
+            TextEncoding::Utf8 => Ok(String::from_utf16(text)?.into_bytes()),
+            TextEncoding::Windows1251 => {
+                let text = String::from_utf16(text)?;
+                let (data, _, had_errors) = WINDOWS_1251.encode(&text);
+                if had_errors {
+                    return Err("Text contains characters not representable in windows-1251".into());
+                }
+                Ok(data.into_owned())
+            }
+        }
+    }
+
+    /// Decodes `data`, invalid sequences are an error unless `replace` is set.
+    pub fn decode(self, data: &[u8], replace: bool) -> Result<String, Box<dyn Error>> {
+        let encoding = match self {
+            TextEncoding::Utf8 => UTF_8,
+            TextEncoding::Utf16Le => UTF_16LE,
+            TextEncoding::Utf16Be => UTF_16BE,
+            TextEncoding::Windows1251 => WINDOWS_1251,
+        };
+        if replace {
+            let (text, _) = encoding.decode_without_bom_handling(data);
+            Ok(text.into_owned())
+        } else {
+            encoding
+                .decode_without_bom_handling_and_without_replacement(data)
+                .map(|x| x.into_owned())
+                .ok_or_else(|| format!("Message body is not valid {}", encoding.name()).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str) -> Vec<u16> {
+        text.encode_utf16().collect()
+    }
+
+    #[test]
+    fn names_are_parsed() {
+        for (name, expected) in [
+            ("", &b"a"[..]),
+            ("UTF-8", b"a"),
+            ("utf16", b"a\0"),
+            ("UTF-16BE", b"\0a"),
+            ("cp1251", b"a"),
+        ] {
+            let encoding = TextEncoding::parse(name).unwrap();
+            assert_eq!(encoding.encode(&utf16("a")).unwrap(), expected, "{name}");
+        }
+        assert_eq!(
+            TextEncoding::parse("koi8-r").err().unwrap().to_string(),
+            "Unknown encoding: koi8-r"
+        );
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let text = "Привет, мир";
+        for encoding in [
+            TextEncoding::Utf8,
+            TextEncoding::Utf16Le,
+            TextEncoding::Utf16Be,
+            TextEncoding::Windows1251,
+        ] {
+            let data = encoding.encode(&utf16(text)).unwrap();
+            assert_eq!(encoding.decode(&data, false).unwrap(), text);
+        }
+        assert_eq!(
+            TextEncoding::Windows1251.encode(&utf16("Привет")).unwrap(),
+            [0xCF, 0xF0, 0xE8, 0xE2, 0xE5, 0xF2]
+        );
+    }
+
+    #[test]
+    fn unrepresentable_text_is_rejected() {
+        assert!(TextEncoding::Windows1251.encode(&utf16("日本")).is_err());
+        // unpaired surrogate
+        assert!(TextEncoding::Utf8.encode(&[0xD800]).is_err());
+    }
+
+    #[test]
+    fn invalid_data_is_replaced_on_request() {
+        let data = [b'a', 0xFF];
+        assert_eq!(
+            TextEncoding::Utf8
+                .decode(&data, false)
+                .err()
+                .unwrap()
+                .to_string(),
+            "Message body is not valid UTF-8"
+        );
+        assert_eq!(TextEncoding::Utf8.decode(&data, true).unwrap(), "a\u{fffd}");
+        // a BOM is kept as data
+        assert_eq!(
+            TextEncoding::Utf8.decode(b"\xEF\xBB\xBFa", false).unwrap(),
+            "\u{feff}a"
+        );
+    }
+}
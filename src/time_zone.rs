@@ -0,0 +1,167 @@
+use std::error::Error;
+
+use addin1c::Tm;
+use chrono::{DateTime, FixedOffset, Local, LocalResult, NaiveDateTime, TimeZone as _, Utc};
+
+/// How 1C dates, which carry no time zone, map to timestamps in both directions.
+#[derive(Clone, Copy, Default)]
+pub enum TimeZone {
+    #[default]
+    Utc,
+    /// The time zone of the machine running the component.
+    Local,
+    Fixed(FixedOffset),
+}
+
+pub fn from_unix_millis(millis: i64) -> Result<DateTime<Utc>, Box<dyn Error>> {
+    DateTime::from_timestamp_millis(millis)
+        .ok_or_else(|| format!("Invalid timestamp {millis}").into())
+}
+
+impl TimeZone {
+    /// Parses `utc`, `local` or an offset such as `+03:00`.
+    pub fn parse(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name.to_lowercase().as_str() {
+            "" | "utc" | "z" => Ok(TimeZone::Utc),
+            "local" => Ok(TimeZone::Local),
+            _ => {
+                let offset = name
+                    .parse::<FixedOffset>()
+                    .map_err(|_| format!("Unknown time zone: {name}"))?;
+                Ok(TimeZone::Fixed(offset))
+            }
+        }
+    }
+
+    /// The wall clock time in this zone, milliseconds are dropped by the 1C date.
+    pub fn timestamp_to_date(self, timestamp: DateTime<Utc>) -> Tm {
+        match self {
+            TimeZone::Utc => timestamp.naive_utc().into(),
+            TimeZone::Local => timestamp.with_timezone(&Local).naive_local().into(),
+            TimeZone::Fixed(offset) => timestamp.with_timezone(&offset).naive_local().into(),
+        }
+    }
+
+    pub fn date_to_timestamp(self, date: Tm) -> Result<DateTime<Utc>, Box<dyn Error>> {
+        self.localize(date.into())
+    }
+
+    /// Ambiguous local time, when clocks go back, resolves to the earlier instant.
+    pub fn localize(self, datetime: NaiveDateTime) -> Result<DateTime<Utc>, Box<dyn Error>> {
+        let result = match self {
+            TimeZone::Utc => return Ok(datetime.and_utc()),
+            TimeZone::Local => Local.from_local_datetime(&datetime).map(|x| x.to_utc()),
+            TimeZone::Fixed(offset) => offset.from_local_datetime(&datetime).map(|x| x.to_utc()),
+        };
+        match result {
+            LocalResult::Single(x) | LocalResult::Ambiguous(x, _) => Ok(x),
+            LocalResult::None => {
+                Err(format!("Time {datetime} does not exist in the time zone").into())
+            }
+        }
+    }
+
+    /// RFC 3339 with milliseconds and the offset of this zone.
+    pub fn format(self, timestamp: DateTime<Utc>) -> String {
+        use chrono::SecondsFormat::Millis;
+        match self {
+            TimeZone::Utc => timestamp.to_rfc3339_opts(Millis, true),
+            TimeZone::Local => timestamp
+                .with_timezone(&Local)
+                .to_rfc3339_opts(Millis, false),
+            TimeZone::Fixed(offset) => timestamp
+                .with_timezone(&offset)
+                .to_rfc3339_opts(Millis, false),
+        }
+    }
+
+    /// Parses RFC 3339, a time without an offset is taken in this zone.
+    pub fn parse_timestamp(self, text: &str) -> Result<DateTime<Utc>, Box<dyn Error>> {
+        if let Ok(x) = DateTime::parse_from_rfc3339(text) {
+            return Ok(x.to_utc());
+        }
+        let datetime = NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f")
+            .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f"))
+            .map_err(|e| format!("Invalid timestamp '{text}': {e}"))?;
+        self.localize(datetime)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f").unwrap()
+    }
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().to_utc()
+    }
+
+    #[test]
+    fn zones_are_parsed() {
+        assert!(matches!(TimeZone::parse("").unwrap(), TimeZone::Utc));
+        assert!(matches!(TimeZone::parse("UTC").unwrap(), TimeZone::Utc));
+        assert!(matches!(TimeZone::parse("Local").unwrap(), TimeZone::Local));
+        match TimeZone::parse("+03:00").unwrap() {
+            TimeZone::Fixed(x) => assert_eq!(x.local_minus_utc(), 3 * 3600),
+            _ => panic!("fixed offset expected"),
+        }
+        assert_eq!(
+            TimeZone::parse("Europe/Moscow").err().unwrap().to_string(),
+            "Unknown time zone: Europe/Moscow"
+        );
+    }
+
+    #[test]
+    fn dates_follow_the_zone() {
+        let zone = TimeZone::parse("+03:00").unwrap();
+        let timestamp = utc("2024-01-02T00:04:05.678Z");
+        let date: NaiveDateTime = zone.timestamp_to_date(timestamp).into();
+        assert_eq!(date, datetime("2024-01-02 03:04:05"));
+        assert_eq!(
+            zone.date_to_timestamp(date.into()).unwrap(),
+            utc("2024-01-02T00:04:05Z")
+        );
+        let date: NaiveDateTime = TimeZone::Utc.timestamp_to_date(timestamp).into();
+        assert_eq!(date, datetime("2024-01-02 00:04:05"));
+    }
+
+    #[test]
+    fn timestamps_are_formatted_with_the_offset() {
+        let timestamp = utc("2024-01-02T00:04:05.678Z");
+        assert_eq!(TimeZone::Utc.format(timestamp), "2024-01-02T00:04:05.678Z");
+        assert_eq!(
+            TimeZone::parse("-02:30").unwrap().format(timestamp),
+            "2024-01-01T21:34:05.678-02:30"
+        );
+    }
+
+    #[test]
+    fn timestamps_without_offset_use_the_zone() {
+        let zone = TimeZone::parse("+03:00").unwrap();
+        let expected = utc("2024-01-02T00:04:05.678Z");
+        for text in [
+            "2024-01-02T00:04:05.678Z",
+            "2024-01-02T03:04:05.678+03:00",
+            "2024-01-02T03:04:05.678",
+            "2024-01-02 03:04:05.678",
+        ] {
+            assert_eq!(zone.parse_timestamp(text).unwrap(), expected, "{text}");
+        }
+        assert!(zone.parse_timestamp("02.01.2024").is_err());
+    }
+
+    #[test]
+    fn unix_millis_are_checked() {
+        assert_eq!(
+            from_unix_millis(1704153845678).unwrap(),
+            utc("2024-01-02T00:04:05.678Z")
+        );
+        assert_eq!(
+            from_unix_millis(i64::MAX).err().unwrap().to_string(),
+            format!("Invalid timestamp {}", i64::MAX)
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use std::error::Error;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use p12_keystore::{KeyStore, KeyStoreEntry};
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer},
+    RootCertStore,
+};
+
+/// Client certificate chain, private key and trusted certificates in PEM format.
+pub struct PemBundle {
+    pub certificates: Vec<u8>,
+    pub private_key: Vec<u8>,
+    pub root_certificates: Vec<u8>,
+}
+
+/// Extracts the first private key with its chain and all trusted certificates.
+pub fn pkcs12_to_pem(data: &[u8], password: &str) -> Result<PemBundle, Box<dyn Error>> {
+    let key_store = KeyStore::from_pkcs12(data, password)
+        .map_err(|e| format!("Invalid PKCS#12 bundle: {e}"))?;
+    let (_, chain) = key_store
+        .private_key_chain()
+        .ok_or("PKCS#12 bundle does not contain a private key")?;
+
+    let mut bundle = PemBundle {
+        certificates: Vec::new(),
+        private_key: pem("PRIVATE KEY", chain.key()).into_bytes(),
+        root_certificates: Vec::new(),
+    };
+    for certificate in chain.chain() {
+        bundle
+            .certificates
+            .extend(pem("CERTIFICATE", certificate.as_der()).bytes());
+    }
+    for (_, entry) in key_store.entries() {
+        if let KeyStoreEntry::Certificate(certificate) = entry {
+            bundle
+                .root_certificates
+                .extend(pem("CERTIFICATE", certificate.as_der()).bytes());
+        }
+    }
+    Ok(bundle)
+}
+
+/// Loads the OS trust store, skipping certificates the TLS library cannot parse.
+pub fn system_root_certificates() -> Result<Vec<u8>, Box<dyn Error>> {
+    let result = rustls_native_certs::load_native_certs();
+    let mut certificates = Vec::new();
+    for certificate in result.certs {
+        if RootCertStore::empty().add(certificate.clone()).is_ok() {
+            certificates.extend(pem("CERTIFICATE", &certificate).bytes());
+        }
+    }
+    if certificates.is_empty() {
+        let errors = result
+            .errors
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        return Err(format!("No system root certificates found: {}", errors.join("; ")).into());
+    }
+    Ok(certificates)
+}
+
+pub fn parse_certificates(data: &[u8]) -> Result<Vec<CertificateDer<'static>>, Box<dyn Error>> {
+    let certificates = rustls_pemfile::certs(&mut &data[..]).collect::<Result<Vec<_>, _>>()?;
+    if certificates.is_empty() {
+        return Err("No certificates found".into());
+    }
+    Ok(certificates)
+}
+
+pub fn parse_private_key(data: &[u8]) -> Result<PrivateKeyDer<'static>, Box<dyn Error>> {
+    let key = rustls_pemfile::private_key(&mut &data[..])?;
+    Ok(key.ok_or("No private key found")?)
+}
+
+fn pem(label: &str, der: &[u8]) -> String {
+    let encoded = STANDARD.encode(der);
+    let mut result = format!("-----BEGIN {label}-----\n");
+    for line in encoded.as_bytes().chunks(64) {
+        result.push_str(std::str::from_utf8(line).unwrap());
+        result.push('\n');
+    }
+    result.push_str(&format!("-----END {label}-----\n"));
+    result
+}